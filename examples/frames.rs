@@ -0,0 +1,39 @@
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use winit_async::event::{Event, WindowEvent};
+use winit_async::UserEvent;
+
+fn main() {
+    let event_loop = EventLoop::<UserEvent>::with_user_event();
+
+    winit_async::run(event_loop, |target, mut events| async move {
+        let window = target
+            .create_window(WindowBuilder::new().with_title("Rendering as fast as we can"))
+            .await
+            .unwrap();
+
+        let mut frames = winit_async::window_frames(&window);
+
+        loop {
+            match future::select(frames.next(), events.next()).await {
+                Either::Left((Some(frame), _)) => {
+                    // A real application would render here.
+                    if let Some(delta) = frame.delta() {
+                        println!("{:.1} fps", 1.0 / delta.as_secs_f64());
+                    }
+                }
+                Either::Right((
+                    Some(Event::WindowEvent {
+                        event: WindowEvent::CloseRequested,
+                        ..
+                    }),
+                    _,
+                )) => break,
+                Either::Right((Some(_), _)) => {}
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            }
+        }
+    })
+}
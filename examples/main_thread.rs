@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use futures_util::StreamExt;
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use winit_async::event::{Event, WindowEvent};
+use winit_async::UserEvent;
+
+/// A bare-bones executor for the worker thread, standing in for whatever async runtime it'd
+/// really be using.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+fn main() {
+    let event_loop = EventLoop::<UserEvent>::with_user_event();
+
+    winit_async::run(event_loop, |target, mut events| async move {
+        let handle = target.main_thread_handle();
+        let (tx, rx) = async_channel::bounded(1);
+        thread::spawn(move || {
+            block_on(async {
+                let window = handle
+                    .create_window(|| WindowBuilder::new().with_title("Made on a worker thread"))
+                    .await
+                    .unwrap();
+                let monitor = handle
+                    .run_on_main(|target| target.primary_monitor().and_then(|m| m.name()))
+                    .await;
+                window.set_title(&format!(
+                    "Made on a worker thread, shown on {}",
+                    monitor.as_deref().unwrap_or("an unknown monitor")
+                ));
+                let _ = tx.send(window).await;
+            })
+        });
+
+        let window = rx.recv().await.unwrap();
+        while let Some(event) = events.next().await {
+            match event {
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    window_id,
+                } if window_id == window.id() => break,
+                _ => (),
+            }
+        }
+    })
+}
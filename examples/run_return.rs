@@ -0,0 +1,47 @@
+use futures_util::StreamExt;
+use winit::event::{ElementState, VirtualKeyCode};
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use winit_async::event::{Event, KeyboardInput, WindowEvent};
+use winit_async::UserEvent;
+
+fn main() {
+    let mut event_loop = EventLoop::<UserEvent>::with_user_event();
+
+    let window = WindowBuilder::new()
+        .with_title("Press a number key")
+        .with_inner_size(winit::dpi::LogicalSize::new(128.0, 128.0))
+        .build(&event_loop)
+        .unwrap();
+
+    let choice = winit_async::run_return(&mut event_loop, |_, mut events| async move {
+        while let Some(event) = events.next().await {
+            if let Event::WindowEvent { event, window_id } = event {
+                if window_id != window.id() {
+                    continue;
+                }
+                match event {
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } if (VirtualKeyCode::Key1..=VirtualKeyCode::Key9).contains(&key) => {
+                        return Some(key as u32 - VirtualKeyCode::Key1 as u32 + 1);
+                    }
+                    WindowEvent::CloseRequested => return None,
+                    _ => {}
+                }
+            }
+        }
+        None
+    });
+
+    match choice {
+        Ok(Some(choice)) => println!("You picked {choice}"),
+        Ok(None) | Err(_) => println!("You didn't pick anything"),
+    }
+}
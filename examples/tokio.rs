@@ -0,0 +1,48 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::net::{TcpListener, TcpStream};
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use winit_async::event::{Event, WindowEvent};
+use winit_async::UserEvent;
+
+fn main() {
+    let event_loop = EventLoop::<UserEvent>::with_user_event();
+
+    winit_async::run(event_loop, |target, mut events| async move {
+        let window = target
+            .create_window(WindowBuilder::new().with_title("Waiting for a connection..."))
+            .await
+            .unwrap();
+        let window = Rc::new(window);
+
+        let _server = winit_async::spawn_local({
+            let window = window.clone();
+            async move {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                // This runs on tokio's threads, rather than the event loop's.
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    TcpStream::connect(addr).await
+                });
+
+                let (_stream, peer) = listener.accept().await.unwrap();
+                window.set_title(&format!("Connected to {peer}"));
+            }
+        });
+
+        while let Some(event) = events.next().await {
+            match event {
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    window_id,
+                } if window_id == window.id() => break,
+                _ => (),
+            }
+        }
+    })
+}
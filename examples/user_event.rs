@@ -0,0 +1,49 @@
+use std::thread;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use winit_async::event::{Event, WindowEvent};
+use winit_async::UserEvent;
+
+#[derive(Debug)]
+enum Progress {
+    Step(u32),
+    Done,
+}
+
+fn main() {
+    let event_loop = EventLoop::<UserEvent<Progress>>::with_user_event();
+
+    let window = WindowBuilder::new()
+        .with_title("Waiting for a worker...")
+        .with_inner_size(winit::dpi::LogicalSize::new(128.0, 128.0))
+        .build(&event_loop)
+        .unwrap();
+
+    let proxy = event_loop.create_proxy();
+    thread::spawn(move || {
+        for i in 0..5 {
+            thread::sleep(Duration::from_millis(500));
+            if proxy.send_event(Progress::Step(i).into()).is_err() {
+                return;
+            }
+        }
+        let _ = proxy.send_event(Progress::Done.into());
+    });
+
+    winit_async::run(event_loop, |_, mut events| async move {
+        while let Some(event) = events.next().await {
+            match event {
+                Event::UserEvent(Progress::Step(i)) => window.set_title(&format!("Step {i}")),
+                Event::UserEvent(Progress::Done) => break,
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    window_id,
+                } if window_id == window.id() => break,
+                _ => (),
+            }
+        }
+    })
+}
@@ -1,18 +1,22 @@
 use futures_util::StreamExt;
-use winit::event::{Event, WindowEvent};
 use winit::event_loop::EventLoop;
 use winit::window::WindowBuilder;
+use winit_async::event::{Event, WindowEvent};
+use winit_async::UserEvent;
 
 fn main() {
-    let event_loop = EventLoop::new();
+    let event_loop = EventLoop::<UserEvent>::with_user_event();
 
-    let window = WindowBuilder::new()
-        .with_title("A fantastic window!")
-        .with_inner_size(winit::dpi::LogicalSize::new(128.0, 128.0))
-        .build(&event_loop)
-        .unwrap();
+    winit_async::run(event_loop, |target, mut events| async move {
+        let window = target
+            .create_window(
+                WindowBuilder::new()
+                    .with_title("A fantastic window!")
+                    .with_inner_size(winit::dpi::LogicalSize::new(128.0, 128.0)),
+            )
+            .await
+            .unwrap();
 
-    winit_async::run(event_loop, |_, mut events| async move {
         while let Some(event) = events.next().await {
             println!("{event:?}");
 
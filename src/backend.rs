@@ -0,0 +1,153 @@
+//! The event loops that [`run`](crate::run) can drive.
+//!
+//! [`run`](crate::run) only needs a handful of things from an event loop: a way to be called
+//! with each event, somewhere to put the resulting [`ControlFlow`], and a proxy to wake it up from
+//! other threads. [`Backend`] captures exactly that, which lets it drive winit's [`EventLoop`] as
+//! well as the in-memory [`FakeEventLoop`](fake::FakeEventLoop) used for testing.
+
+use winit::event::Event;
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopWindowTarget};
+
+use crate::UserEvent;
+
+pub mod fake;
+
+/// An event loop which can be driven by [`run`](crate::run).
+pub trait Backend {
+    /// The type of the application's user events.
+    type UserEvent: 'static;
+    /// The window target passed to [`run`](crate::run)'s callback.
+    type Target: 'static;
+    /// A handle for sending events to the event loop from any thread.
+    type Proxy: Proxy<Self::UserEvent>;
+
+    /// Whether [`run`](Self::run) returns once the event loop has been destroyed, leaving the
+    /// caller to exit the process like [`EventLoop::run`] would.
+    ///
+    /// [`run`](crate::run) can then resume a panic from the future after the event loop is gone,
+    /// rather than from inside its final callback, where the panic would have to unwind through
+    /// the event loop.
+    const RETURNS_BEFORE_EXIT: bool = false;
+
+    /// Creates a new [`Proxy`] for this event loop.
+    fn create_proxy(&self) -> Self::Proxy;
+
+    /// Runs the event loop, calling `handler` with every event it receives.
+    ///
+    /// `handler` sets the [`ControlFlow`] the event loop should follow afterwards. This may never
+    /// return, like [`EventLoop::run`].
+    fn run<H>(self, handler: H)
+    where
+        H: 'static + FnMut(Event<'_, UserEvent<Self::UserEvent>>, &Self::Target, &mut ControlFlow);
+}
+
+/// A handle for sending events to an event loop from any thread.
+pub trait Proxy<T: 'static>: Send + 'static {
+    /// Sends an event to the event loop, waking it up.
+    ///
+    /// If the event loop has already exited, the event is silently dropped.
+    fn send(&self, event: UserEvent<T>);
+}
+
+/// A [`Backend`] which can return control to the caller once it exits, for use with
+/// [`run_return`](crate::run_return).
+pub trait RunReturn: Backend {
+    /// Runs the event loop until `handler` sets [`ControlFlow::Exit`], calling it with every event
+    /// it receives.
+    fn run_return<H>(&mut self, handler: H)
+    where
+        H: FnMut(Event<'_, UserEvent<Self::UserEvent>>, &Self::Target, &mut ControlFlow);
+}
+
+impl<T: Send + 'static> Backend for EventLoop<UserEvent<T>> {
+    type UserEvent = T;
+    type Target = EventLoopWindowTarget<UserEvent<T>>;
+    type Proxy = EventLoopProxy<UserEvent<T>>;
+
+    const RETURNS_BEFORE_EXIT: bool = platform::HAS_RUN_RETURN;
+
+    fn create_proxy(&self) -> Self::Proxy {
+        EventLoop::create_proxy(self)
+    }
+
+    fn run<H>(self, handler: H)
+    where
+        H: 'static + FnMut(Event<'_, UserEvent<T>>, &Self::Target, &mut ControlFlow),
+    {
+        platform::run(self, handler)
+    }
+}
+
+#[cfg(any(
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "android",
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+// These are the platforms winit supports `run_return` on.
+mod platform {
+    use winit::event::Event;
+    use winit::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
+    use winit::platform::run_return::EventLoopExtRunReturn;
+
+    use super::RunReturn;
+    use crate::UserEvent;
+
+    pub(super) const HAS_RUN_RETURN: bool = true;
+
+    /// Runs the event loop through `run_return`, so that it returns once it's been destroyed.
+    pub(super) fn run<T, H>(mut event_loop: EventLoop<UserEvent<T>>, handler: H)
+    where
+        H: FnMut(Event<'_, UserEvent<T>>, &EventLoopWindowTarget<UserEvent<T>>, &mut ControlFlow),
+    {
+        EventLoopExtRunReturn::run_return(&mut event_loop, handler)
+    }
+
+    impl<T: Send + 'static> RunReturn for EventLoop<UserEvent<T>> {
+        fn run_return<H>(&mut self, handler: H)
+        where
+            H: FnMut(Event<'_, UserEvent<T>>, &Self::Target, &mut ControlFlow),
+        {
+            EventLoopExtRunReturn::run_return(self, handler)
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "android",
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+mod platform {
+    use winit::event::Event;
+    use winit::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
+
+    use crate::UserEvent;
+
+    pub(super) const HAS_RUN_RETURN: bool = false;
+
+    pub(super) fn run<T, H>(event_loop: EventLoop<UserEvent<T>>, handler: H)
+    where
+        H: 'static
+            + FnMut(Event<'_, UserEvent<T>>, &EventLoopWindowTarget<UserEvent<T>>, &mut ControlFlow),
+    {
+        event_loop.run(handler)
+    }
+}
+
+impl<T: Send + 'static> Proxy<T> for EventLoopProxy<UserEvent<T>> {
+    fn send(&self, event: UserEvent<T>) {
+        // This only returns an error if the event loop is closed, in which case there's nothing
+        // to deliver the event to anyway.
+        let _ = self.send_event(event);
+    }
+}
@@ -0,0 +1,448 @@
+//! An in-memory event loop, for testing code built on [`run`](crate::run) without a display
+//! server.
+//!
+//! A [`FakeEventLoop`] delivers events pushed onto it in the same order as a real winit event
+//! loop would: each loop iteration starts with [`Event::NewEvents`], followed by all the queued
+//! events, then [`Event::MainEventsCleared`], any queued [`Event::RedrawRequested`] events, and
+//! [`Event::RedrawEventsCleared`]. It follows the [`ControlFlow`] set by the handler between
+//! iterations, except that by default it never waits for events from other threads: once it runs
+//! out of events in [`ControlFlow::Wait`], the loop is destroyed and [`run`](crate::run) returns.
+//! Use [`FakeEventLoop::wait_for_proxies`] to have it wait for them instead, e.g. to test code
+//! which hands work off to other threads.
+//!
+//! A fake event loop can be run again after it exits, with [`run_return`](crate::run_return).
+
+use std::collections::VecDeque;
+use std::iter;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalSize;
+use winit::event::StartCause;
+use winit::event_loop::ControlFlow;
+
+use super::{Backend, Proxy, RunReturn};
+use crate::event::Event;
+use crate::UserEvent;
+
+/// An event loop which delivers a scripted sequence of events.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Debug)]
+pub struct FakeEventLoop<T: 'static = ()> {
+    shared: Arc<Shared<T>>,
+    /// How long to wait for an event in [`ControlFlow::Wait`] before giving up, if at all.
+    proxy_timeout: Option<Duration>,
+}
+
+/// The window target of a [`FakeEventLoop`].
+///
+/// There aren't any windows in a fake event loop, so this doesn't do anything.
+#[derive(Debug)]
+pub struct FakeTarget {
+    _private: (),
+}
+
+/// A handle to a [`FakeEventLoop`], which can be used from any thread.
+#[derive(Debug)]
+pub struct FakeProxy<T: 'static = ()> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+struct Shared<T: 'static> {
+    queue: Mutex<VecDeque<Event<UserEvent<T>>>>,
+    /// Notified whenever an event is pushed onto `queue`.
+    pushed: Condvar,
+    control_flows: Mutex<Vec<ControlFlow>>,
+}
+
+impl<T> FakeEventLoop<T> {
+    /// Creates an event loop with no events queued up.
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(VecDeque::new()),
+                pushed: Condvar::new(),
+                control_flows: Mutex::new(Vec::new()),
+            }),
+            proxy_timeout: None,
+        }
+    }
+
+    /// Makes the event loop wait for events sent through its [`FakeProxy`]s once it runs out of
+    /// events in [`ControlFlow::Wait`], like a real event loop, rather than being destroyed.
+    ///
+    /// The loop then only exits once the handler asks it to. If nothing arrives within
+    /// `timeout`, whatever the handler is waiting on has presumably hung, so the event loop
+    /// panics to report it rather than blocking forever.
+    pub fn wait_for_proxies(mut self, timeout: Duration) -> Self {
+        self.proxy_timeout = Some(timeout);
+        self
+    }
+
+    /// Queues up an event to be delivered once the event loop is running.
+    pub fn push_event(&self, event: Event<T>) {
+        self.shared
+            .push_event(event.map_user_event(UserEvent::from));
+    }
+
+    /// Creates a new [`FakeProxy`] for this event loop.
+    pub fn create_proxy(&self) -> FakeProxy<T> {
+        FakeProxy {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Default for FakeEventLoop<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FakeProxy<T> {
+    /// Queues up an event to be delivered by the event loop.
+    pub fn push_event(&self, event: Event<T>) {
+        self.shared
+            .push_event(event.map_user_event(UserEvent::from));
+    }
+
+    /// Returns the [`ControlFlow`] the event loop was left with after each event it delivered,
+    /// in order.
+    pub fn control_flows(&self) -> Vec<ControlFlow> {
+        self.shared.control_flows.lock().unwrap().clone()
+    }
+}
+
+impl<T> Clone for FakeProxy<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    fn push_event(&self, event: Event<UserEvent<T>>) {
+        self.queue.lock().unwrap().push_back(event);
+        self.pushed.notify_all();
+    }
+
+    fn pop_event(&self) -> Option<Event<UserEvent<T>>> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    /// Waits until there's an event in the queue or `deadline` passes, returning whether there's
+    /// an event.
+    fn wait_for_event(&self, deadline: Instant) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if !queue.is_empty() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            queue = self.pushed.wait_timeout(queue, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl<T: Send + 'static> Backend for FakeEventLoop<T> {
+    type UserEvent = T;
+    type Target = FakeTarget;
+    type Proxy = FakeProxy<T>;
+
+    fn create_proxy(&self) -> Self::Proxy {
+        FakeEventLoop::create_proxy(self)
+    }
+
+    fn run<H>(self, handler: H)
+    where
+        H: 'static + FnMut(winit::event::Event<'_, UserEvent<T>>, &Self::Target, &mut ControlFlow),
+    {
+        self.drive(handler)
+    }
+}
+
+impl<T: Send + 'static> RunReturn for FakeEventLoop<T> {
+    fn run_return<H>(&mut self, handler: H)
+    where
+        H: FnMut(winit::event::Event<'_, UserEvent<T>>, &Self::Target, &mut ControlFlow),
+    {
+        self.drive(handler)
+    }
+}
+
+impl<T: Send + 'static> FakeEventLoop<T> {
+    fn drive<H>(&self, mut handler: H)
+    where
+        H: FnMut(winit::event::Event<'_, UserEvent<T>>, &FakeTarget, &mut ControlFlow),
+    {
+        let target = FakeTarget { _private: () };
+
+        let mut control_flow = ControlFlow::default();
+        let mut dispatch = |event: Event<UserEvent<T>>, control_flow: &mut ControlFlow| {
+            let mut size = PhysicalSize::default();
+            let (event, slot) = event.into_winit(&mut size);
+
+            let exiting = *control_flow == ControlFlow::Exit;
+            handler(event, &target, control_flow);
+            // Like winit, don't let the handler change its mind once it's asked to exit.
+            if exiting {
+                *control_flow = ControlFlow::Exit;
+            }
+
+            if let Some(slot) = slot {
+                slot.set(size);
+            }
+            self.shared
+                .control_flows
+                .lock()
+                .unwrap()
+                .push(*control_flow);
+        };
+
+        let mut cause = StartCause::Init;
+        'iterations: loop {
+            dispatch(Event::NewEvents(cause), &mut control_flow);
+            let mut redraws = Vec::new();
+            while control_flow != ControlFlow::Exit {
+                match self.shared.pop_event() {
+                    // Like winit, leave redrawing until everything else has been handled.
+                    Some(event @ Event::RedrawRequested(_)) => redraws.push(event),
+                    Some(event) => dispatch(event, &mut control_flow),
+                    None => break,
+                }
+            }
+            let end = iter::once(Event::MainEventsCleared)
+                .chain(redraws)
+                .chain([Event::RedrawEventsCleared]);
+            for event in end {
+                if control_flow == ControlFlow::Exit {
+                    break 'iterations;
+                }
+                dispatch(event, &mut control_flow);
+            }
+
+            let start = Instant::now();
+            cause = match control_flow {
+                ControlFlow::Poll => StartCause::Poll,
+                ControlFlow::Wait => {
+                    let timeout = self.proxy_timeout.unwrap_or_default();
+                    if !self.shared.wait_for_event(start + timeout) {
+                        match self.proxy_timeout {
+                            Some(timeout) => panic!(
+                                "the fake event loop waited {timeout:?} for an event without \
+                                 receiving one"
+                            ),
+                            // There's nothing left to wait for.
+                            None => break,
+                        }
+                    }
+                    StartCause::WaitCancelled {
+                        start,
+                        requested_resume: None,
+                    }
+                }
+                ControlFlow::WaitUntil(requested_resume) => {
+                    if self.shared.wait_for_event(requested_resume) {
+                        StartCause::WaitCancelled {
+                            start,
+                            requested_resume: Some(requested_resume),
+                        }
+                    } else {
+                        StartCause::ResumeTimeReached {
+                            start,
+                            requested_resume,
+                        }
+                    }
+                }
+                ControlFlow::Exit => break,
+            };
+        }
+
+        dispatch(Event::LoopDestroyed, &mut control_flow);
+    }
+}
+
+impl<T: Send + 'static> Proxy<T> for FakeProxy<T> {
+    fn send(&self, event: UserEvent<T>) {
+        self.shared.push_event(Event::UserEvent(event));
+    }
+}
+
+/// Returns the ID of the window that tests send made-up window events from.
+#[cfg(test)]
+pub(crate) fn window_id() -> winit::window::WindowId {
+    // SAFETY: a fake event loop doesn't have any real windows for this to be confused with.
+    unsafe { winit::window::WindowId::dummy() }
+}
+
+/// Returns the ID of the device that tests send made-up input events from.
+#[cfg(test)]
+pub(crate) fn device_id() -> winit::event::DeviceId {
+    // SAFETY: nor does it have any real devices.
+    unsafe { winit::event::DeviceId::dummy() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use winit::event::StartCause;
+    use winit::event_loop::ControlFlow;
+
+    use super::FakeEventLoop;
+    use crate::backend::RunReturn;
+    use crate::event::Event;
+    use crate::{Message, UserEvent};
+
+    /// The parts of an event the tests care about.
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        NewEvents(StartCause),
+        User(u32),
+        MainEventsCleared,
+        RedrawRequested,
+        RedrawEventsCleared,
+        LoopDestroyed,
+    }
+
+    /// Runs `event_loop`, using `control_flow` to pick the control flow after each event, and
+    /// returns the events it delivered.
+    fn record(
+        event_loop: &mut FakeEventLoop<u32>,
+        mut control_flow: impl FnMut(&Seen) -> ControlFlow,
+    ) -> Vec<Seen> {
+        let mut seen = Vec::new();
+        event_loop.run_return(|event, _, flow| {
+            let event = match event {
+                winit::event::Event::NewEvents(cause) => Seen::NewEvents(cause),
+                winit::event::Event::UserEvent(UserEvent(Message::User(n))) => Seen::User(n),
+                winit::event::Event::MainEventsCleared => Seen::MainEventsCleared,
+                winit::event::Event::RedrawRequested(_) => Seen::RedrawRequested,
+                winit::event::Event::RedrawEventsCleared => Seen::RedrawEventsCleared,
+                winit::event::Event::LoopDestroyed => Seen::LoopDestroyed,
+                event => panic!("unexpected event {event:?}"),
+            };
+            *flow = control_flow(&event);
+            seen.push(event);
+        });
+        seen
+    }
+
+    #[test]
+    fn delivers_events_in_order() {
+        let mut event_loop = FakeEventLoop::new();
+        event_loop.push_event(Event::UserEvent(1));
+        event_loop.push_event(Event::UserEvent(2));
+
+        let seen = record(&mut event_loop, |_| ControlFlow::Wait);
+        assert_eq!(
+            seen,
+            [
+                Seen::NewEvents(StartCause::Init),
+                Seen::User(1),
+                Seen::User(2),
+                Seen::MainEventsCleared,
+                Seen::RedrawEventsCleared,
+                Seen::LoopDestroyed,
+            ]
+        );
+    }
+
+    #[test]
+    fn delivers_redraws_after_main_events_cleared() {
+        let mut event_loop = FakeEventLoop::new();
+        event_loop.push_event(Event::RedrawRequested(super::window_id()));
+        event_loop.push_event(Event::UserEvent(1));
+
+        let seen = record(&mut event_loop, |_| ControlFlow::Wait);
+        assert_eq!(
+            seen,
+            [
+                Seen::NewEvents(StartCause::Init),
+                Seen::User(1),
+                Seen::MainEventsCleared,
+                Seen::RedrawRequested,
+                Seen::RedrawEventsCleared,
+                Seen::LoopDestroyed,
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_skips_the_rest_of_the_events() {
+        let mut event_loop = FakeEventLoop::new();
+        for n in 1..=3 {
+            event_loop.push_event(Event::UserEvent(n));
+        }
+
+        let seen = record(&mut event_loop, |event| match event {
+            Seen::User(1) => ControlFlow::Exit,
+            // The loop shouldn't let this override the exit.
+            _ => ControlFlow::Poll,
+        });
+        assert_eq!(
+            seen,
+            [
+                Seen::NewEvents(StartCause::Init),
+                Seen::User(1),
+                Seen::LoopDestroyed,
+            ]
+        );
+        let control_flows = event_loop.create_proxy().control_flows();
+        assert_eq!(control_flows.last(), Some(&ControlFlow::Exit));
+    }
+
+    #[test]
+    fn wait_until_resumes_at_the_deadline() {
+        let mut event_loop = FakeEventLoop::new();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(20);
+
+        let seen = record(&mut event_loop, |event| match event {
+            Seen::NewEvents(StartCause::Init) => ControlFlow::WaitUntil(deadline),
+            Seen::NewEvents(_) => ControlFlow::Exit,
+            _ => ControlFlow::WaitUntil(deadline),
+        });
+        assert!(Instant::now() >= deadline);
+        assert!(matches!(
+            seen[3],
+            Seen::NewEvents(StartCause::ResumeTimeReached { requested_resume, .. })
+                if requested_resume == deadline
+        ));
+    }
+
+    #[test]
+    fn waits_for_proxies() {
+        let mut event_loop = FakeEventLoop::new().wait_for_proxies(Duration::from_secs(10));
+        let proxy = event_loop.create_proxy();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            proxy.push_event(Event::UserEvent(7));
+        });
+
+        let seen = record(&mut event_loop, |event| match event {
+            Seen::User(_) => ControlFlow::Exit,
+            _ => ControlFlow::Wait,
+        });
+        sender.join().unwrap();
+        assert!(matches!(
+            seen[3],
+            Seen::NewEvents(StartCause::WaitCancelled { .. })
+        ));
+        assert_eq!(seen[4], Seen::User(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "without receiving one")]
+    fn reports_hangs() {
+        let mut event_loop = FakeEventLoop::new().wait_for_proxies(Duration::from_millis(20));
+        record(&mut event_loop, |_| ControlFlow::Wait);
+    }
+}
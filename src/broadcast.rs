@@ -0,0 +1,357 @@
+//! Fanning events out to every subscribed [`Events`](crate::Events) stream.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_channel::{Receiver, Sender, TrySendError};
+
+use crate::coalesce::{Coalesce, Coalescer};
+use crate::event::Event;
+
+/// What an [`Events`](crate::Events) stream does when its subscriber falls behind.
+///
+/// The capacities of the bounded policies must be non-zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Buffer every event, however many there are.
+    #[default]
+    Unbounded,
+    /// Buffer up to this many events, dropping the oldest ones to make room for new ones.
+    DropOldest(usize),
+    /// Buffer up to this many events, dropping any new ones until there's room again.
+    DropNewest(usize),
+    /// Buffer up to this many events, then merge new ones into each other until there's room
+    /// again.
+    ///
+    /// Once the buffer's full, high-frequency events (as listed in [`Coalesce`]) are held back
+    /// and merged with any more of their kind, as if [`Coalesce::all`] was being used; anything
+    /// else is dropped.
+    Coalesce(usize),
+}
+
+impl LagPolicy {
+    /// Panics if the policy's capacity is 0.
+    pub(crate) fn validate(self) {
+        match self {
+            LagPolicy::Unbounded => {}
+            LagPolicy::DropOldest(cap) | LagPolicy::DropNewest(cap) | LagPolicy::Coalesce(cap) => {
+                assert!(cap > 0, "the `LagPolicy`'s capacity must be non-zero");
+            }
+        }
+    }
+}
+
+/// The senders for all the [`Events`](crate::Events) streams of a runtime.
+#[derive(Debug)]
+pub(crate) struct Broadcast<T: 'static> {
+    subscribers: Vec<Subscriber<T>>,
+    /// How to clone user events, available once someone's subscribed with `T: Clone`.
+    ///
+    /// There's only ever more than one subscriber if this is set.
+    clone_user_event: Option<fn(&T) -> T>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Subscriber<T: 'static> {
+    tx: Sender<Event<T>>,
+    /// Another handle to the subscriber's end of the channel, used to make room for new events
+    /// under [`LagPolicy::DropOldest`].
+    evict: Option<Receiver<Event<T>>>,
+    /// The events that didn't fit in the channel under [`LagPolicy::Coalesce`], which come after
+    /// everything in it.
+    overflow: Option<Arc<Mutex<Coalescer<T>>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The receiving end of a subscription.
+#[derive(Debug)]
+pub(crate) struct Subscription<T: 'static> {
+    pub(crate) rx: Receiver<Event<T>>,
+    pub(crate) overflow: Option<Arc<Mutex<Coalescer<T>>>>,
+    pub(crate) dropped: Arc<AtomicU64>,
+}
+
+impl<T> Broadcast<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+            clone_user_event: None,
+            closed: false,
+        }
+    }
+
+    /// Adds a new subscriber.
+    ///
+    /// This can only be called directly for the first subscriber; after that, go through
+    /// [`subscribe_cloned`](Self::subscribe_cloned).
+    pub(crate) fn subscribe(&mut self, policy: LagPolicy) -> Subscription<T> {
+        let (tx, rx) = match policy {
+            LagPolicy::Unbounded => async_channel::unbounded(),
+            LagPolicy::DropOldest(cap) | LagPolicy::DropNewest(cap) | LagPolicy::Coalesce(cap) => {
+                async_channel::bounded(cap)
+            }
+        };
+        let evict = match policy {
+            LagPolicy::DropOldest(_) => Some(rx.clone()),
+            _ => None,
+        };
+        let overflow = match policy {
+            LagPolicy::Coalesce(_) => Some(Arc::new(Mutex::new(Coalescer::new(Coalesce::all())))),
+            _ => None,
+        };
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        if self.closed {
+            tx.close();
+        } else {
+            self.subscribers.push(Subscriber {
+                tx,
+                evict,
+                overflow: overflow.clone(),
+                dropped: dropped.clone(),
+            });
+        }
+
+        Subscription {
+            rx,
+            overflow,
+            dropped,
+        }
+    }
+
+    pub(crate) fn subscribe_cloned(&mut self, policy: LagPolicy) -> Subscription<T>
+    where
+        T: Clone,
+    {
+        self.clone_user_event = Some(T::clone);
+        self.subscribe(policy)
+    }
+
+    /// Sends an event to every subscriber.
+    pub(crate) fn send(&mut self, event: Event<T>) {
+        let mut event = Some(event);
+
+        let mut i = 0;
+        while i < self.subscribers.len() {
+            // Only clone the event if there's someone else to give it to after this.
+            let last = i + 1 == self.subscribers.len();
+            let event = match self.clone_user_event {
+                Some(clone) if !last => event.as_ref().unwrap().clone_with(clone),
+                _ => event
+                    .take()
+                    .expect("multiple subscribers without a way to clone events"),
+            };
+
+            if self.subscribers[i].send(event) {
+                i += 1;
+            } else {
+                self.subscribers.remove(i);
+            }
+        }
+    }
+
+    /// Ends all the streams.
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+        for subscriber in self.subscribers.drain(..) {
+            subscriber.tx.close();
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// Sends an event to this subscriber, returning whether it's still listening.
+    fn send(&self, event: Event<T>) -> bool {
+        if let Some(overflow) = &self.overflow {
+            return self.send_or_coalesce(overflow, event);
+        }
+
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(event)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                if let Some(evict) = &self.evict {
+                    let _ = evict.try_recv();
+                    let _ = self.tx.try_send(event);
+                }
+            }
+        }
+
+        // Our eviction handle keeps the channel open, so check whether it's the only one left.
+        match &self.evict {
+            Some(_) => self.tx.receiver_count() > 1,
+            None => true,
+        }
+    }
+
+    /// Sends an event under [`LagPolicy::Coalesce`], returning whether the subscriber's still
+    /// listening.
+    fn send_or_coalesce(&self, overflow: &Mutex<Coalescer<T>>, event: Event<T>) -> bool {
+        if self.tx.is_closed() {
+            return false;
+        }
+
+        // Move whatever we can out of the overflow first, so that nothing overtakes it.
+        let mut overflow = overflow.lock().unwrap();
+        overflow.flush_until(|event| self.tx.try_send(event).map_err(TrySendError::into_inner));
+
+        let event = if overflow.is_empty() {
+            match self.tx.try_send(event) {
+                Ok(()) => return true,
+                Err(err) => err.into_inner(),
+            }
+        } else {
+            event
+        };
+
+        if overflow.try_push(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+
+    use futures_core::Stream;
+    use futures_util::future::join;
+    use futures_util::StreamExt;
+    use winit::dpi::PhysicalPosition;
+
+    use super::{Broadcast, LagPolicy, Subscription};
+    use crate::backend::fake::{self, FakeEventLoop};
+    use crate::event::{Event, WindowEvent};
+    use crate::{PollMode, Runtime};
+
+    fn user_events(subscription: &Subscription<u32>) -> Vec<u32> {
+        let mut events = Vec::new();
+        while let Ok(event) = subscription.rx.try_recv() {
+            if let Event::UserEvent(event) = event {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    async fn collect_user_events(events: impl Stream<Item = Event<u32>>) -> Vec<u32> {
+        let user_events = events.filter_map(|event| async move {
+            match event {
+                Event::UserEvent(event) => Some(event),
+                _ => None,
+            }
+        });
+        user_events.collect().await
+    }
+
+    #[test]
+    fn every_stream_gets_every_event() {
+        let event_loop = FakeEventLoop::<u32>::new();
+        for i in 0..5 {
+            event_loop.push_event(Event::UserEvent(i));
+        }
+        let output = Rc::new(RefCell::new(None));
+        let result = output.clone();
+
+        // Poll straight away, so that the second stream is subscribed before any user events.
+        let runtime = Runtime::new().poll_mode(PollMode::Immediate);
+        runtime.run(event_loop, move |_, events| async move {
+            let other = events.subscribe();
+            let streams = join(collect_user_events(events), collect_user_events(other)).await;
+            *result.borrow_mut() = Some(streams);
+        });
+
+        let (root, other) = output.take().unwrap();
+        assert_eq!(root, [0, 1, 2, 3, 4]);
+        assert_eq!(other, root);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let mut broadcast = Broadcast::new();
+        let subscription = broadcast.subscribe(LagPolicy::DropOldest(2));
+        for i in 0..5 {
+            broadcast.send(Event::UserEvent(i));
+        }
+
+        assert_eq!(user_events(&subscription), [3, 4]);
+        assert_eq!(subscription.dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let mut broadcast = Broadcast::new();
+        let subscription = broadcast.subscribe(LagPolicy::DropNewest(2));
+        for i in 0..5 {
+            broadcast.send(Event::UserEvent(i));
+        }
+
+        assert_eq!(user_events(&subscription), [0, 1]);
+        assert_eq!(subscription.dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be non-zero")]
+    fn subscribing_rejects_zero_capacities() {
+        let event_loop = FakeEventLoop::<u32>::new();
+        crate::run(event_loop, |_, events| async move {
+            events.subscribe_with(LagPolicy::DropOldest(0));
+        });
+    }
+
+    #[test]
+    fn dropped_streams_are_forgotten() {
+        let mut broadcast = Broadcast::new();
+        let subscription = broadcast.subscribe_cloned(LagPolicy::DropOldest(2));
+        let kept = broadcast.subscribe_cloned(LagPolicy::Unbounded);
+        drop(subscription);
+
+        broadcast.send(Event::UserEvent(0));
+        assert_eq!(broadcast.subscribers.len(), 1);
+        assert_eq!(user_events(&kept), [0]);
+    }
+
+    #[test]
+    fn coalesce_merges_what_does_not_fit() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        let (window_id, device_id) = (fake::window_id(), fake::device_id());
+        for x in 0..10 {
+            let position = PhysicalPosition::new(x as f64, 0.0);
+            event_loop.push_event(Event::WindowEvent {
+                window_id,
+                event: WindowEvent::CursorMoved {
+                    device_id,
+                    position,
+                },
+            });
+        }
+        event_loop.push_event(Event::UserEvent(0));
+
+        let output = Runtime::new()
+            .lag_policy(LagPolicy::Coalesce(3))
+            .run_return(&mut event_loop, |_, mut events| async move {
+                let mut positions = Vec::new();
+                while let Some(event) = events.next().await {
+                    if let Event::WindowEvent {
+                        event: WindowEvent::CursorMoved { position, .. },
+                        ..
+                    } = event
+                    {
+                        positions.push(position.x);
+                    }
+                }
+                (positions, events.dropped())
+            });
+
+        // The stream fills up with `NewEvents` and the first two cursor movements; the rest of
+        // them are merged into one, and the user event and `MainEventsCleared` are dropped.
+        let (positions, dropped) = output.unwrap();
+        assert_eq!(positions, [0.0, 1.0, 9.0]);
+        assert_eq!(dropped, 2);
+    }
+}
@@ -0,0 +1,606 @@
+//! Merging bursts of high-frequency events before they're sent to the futures.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use winit::event::{DeviceEvent, DeviceId, MouseScrollDelta};
+use winit::window::WindowId;
+
+use crate::event::{Event, WindowEvent};
+
+/// Which high-frequency events get coalesced before being sent to [`Events`](crate::Events) and
+/// [`WindowEvents`](crate::window::WindowEvents) streams, set with
+/// [`Runtime::coalesce`](crate::Runtime::coalesce).
+///
+/// When a coalesced event arrives, it's held back rather than sent straight away. Any more of
+/// the same kind of event (from the same window and device) which arrive before it's sent get
+/// merged into it. It's sent just before the runtime next polls the future, or as soon as an
+/// event from the same window or device arrives which can't be merged into it, so it never gets
+/// moved past one of those. Events from other windows and devices can overtake it.
+///
+/// That means coalescing has the most effect with [`PollMode::Batched`], where the future is
+/// only polled once per batch of events.
+///
+/// Nothing is coalesced by default.
+///
+/// [`PollMode::Batched`]: crate::PollMode::Batched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Coalesce {
+    cursor_moved: bool,
+    resized: bool,
+    axis_motion: bool,
+    mouse_motion: bool,
+    motion: bool,
+    mouse_wheel: bool,
+}
+
+impl Coalesce {
+    /// Doesn't coalesce anything.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Coalesces every kind of event which can be.
+    pub fn all() -> Self {
+        Self {
+            cursor_moved: true,
+            resized: true,
+            axis_motion: true,
+            mouse_motion: true,
+            motion: true,
+            mouse_wheel: true,
+        }
+    }
+
+    /// Sets whether to only keep the latest [`WindowEvent::CursorMoved`].
+    pub fn cursor_moved(mut self, enabled: bool) -> Self {
+        self.cursor_moved = enabled;
+        self
+    }
+
+    /// Sets whether to only keep the latest [`WindowEvent::Resized`].
+    pub fn resized(mut self, enabled: bool) -> Self {
+        self.resized = enabled;
+        self
+    }
+
+    /// Sets whether to only keep the latest [`WindowEvent::AxisMotion`] for each axis.
+    pub fn axis_motion(mut self, enabled: bool) -> Self {
+        self.axis_motion = enabled;
+        self
+    }
+
+    /// Sets whether to sum up the deltas of [`DeviceEvent::MouseMotion`].
+    pub fn mouse_motion(mut self, enabled: bool) -> Self {
+        self.mouse_motion = enabled;
+        self
+    }
+
+    /// Sets whether to sum up the values of [`DeviceEvent::Motion`] for each axis.
+    ///
+    /// Some platforms (e.g. X11 and Windows) send these alongside every
+    /// [`DeviceEvent::MouseMotion`], so coalescing one without the other doesn't get very far.
+    pub fn motion(mut self, enabled: bool) -> Self {
+        self.motion = enabled;
+        self
+    }
+
+    /// Sets whether to sum up the deltas of [`WindowEvent::MouseWheel`] and
+    /// [`DeviceEvent::MouseWheel`].
+    ///
+    /// Wheel events are only merged if their deltas are in the same units, and (for
+    /// [`WindowEvent::MouseWheel`]) they're in the same phase.
+    pub fn mouse_wheel(mut self, enabled: bool) -> Self {
+        self.mouse_wheel = enabled;
+        self
+    }
+
+    /// Returns whether `event` is one of the kinds being coalesced.
+    fn applies_to<T>(&self, event: &Event<T>) -> bool {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CursorMoved { .. } => self.cursor_moved,
+                WindowEvent::Resized(_) => self.resized,
+                WindowEvent::AxisMotion { .. } => self.axis_motion,
+                WindowEvent::MouseWheel { .. } => self.mouse_wheel,
+                _ => false,
+            },
+            Event::DeviceEvent { event, .. } => match event {
+                DeviceEvent::MouseMotion { .. } => self.mouse_motion,
+                DeviceEvent::Motion { .. } => self.motion,
+                DeviceEvent::MouseWheel { .. } => self.mouse_wheel,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Holds back coalesced events until they need to be sent, along with any other events the
+/// runtime wants to hold onto until its next poll.
+#[derive(Debug)]
+pub(crate) struct Coalescer<T: 'static> {
+    config: Coalesce,
+    /// The events being held back, in the order they first arrived.
+    pending: VecDeque<Event<T>>,
+}
+
+/// The result of trying to merge an event into a pending one.
+enum Merge {
+    Merged,
+    /// The events are of the same kind, but can't be merged, so the new one has to come after.
+    Blocked,
+    Unrelated,
+}
+
+impl<T> Coalescer<T> {
+    pub(crate) fn new(config: Coalesce) -> Self {
+        Self {
+            config,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Passes `event` on to `send`, unless it's being coalesced.
+    pub(crate) fn push(&mut self, event: Event<T>, mut send: impl FnMut(Event<T>)) {
+        if let Err(event) = self.try_push(event) {
+            self.flush_before(&event, &mut send);
+            send(event);
+        }
+    }
+
+    /// Holds back `event` until the next [`flush`](Self::flush), whether or not it's being
+    /// coalesced.
+    pub(crate) fn hold(&mut self, event: Event<T>) {
+        if let Err(event) = self.try_push(event) {
+            self.pending.push_back(event);
+        }
+    }
+
+    /// Passes on the events being held back which have to come before `event`: those from the
+    /// same window or device, and those which have to come before one of them in turn.
+    fn flush_before(&mut self, event: &Event<T>, mut send: impl FnMut(Event<T>)) {
+        let mut flushed = vec![false; self.pending.len()];
+        for i in (0..self.pending.len()).rev() {
+            let pending = &self.pending[i];
+            flushed[i] = ordered_against(pending, event)
+                || (i + 1..self.pending.len())
+                    .any(|j| flushed[j] && ordered_against(pending, &self.pending[j]));
+        }
+
+        for (pending, flushed) in mem::take(&mut self.pending).into_iter().zip(flushed) {
+            if flushed {
+                send(pending);
+            } else {
+                self.pending.push_back(pending);
+            }
+        }
+    }
+
+    /// Merges `event` into one of the events being held back, or holds it back as well if
+    /// there's nothing to merge it with.
+    ///
+    /// Returns `event` if it isn't being coalesced, or it can't be held back without getting
+    /// ahead of one of the events it couldn't be merged with.
+    pub(crate) fn try_push(&mut self, event: Event<T>) -> Result<(), Event<T>> {
+        if !self.config.applies_to(&event) {
+            return Err(event);
+        }
+
+        for pending in self.pending.iter_mut().rev() {
+            // Events which are only being held back can't be merged into, or overtaken by
+            // anything they have to stay in order with.
+            if !self.config.applies_to(pending) {
+                if ordered_against(pending, &event) {
+                    return Err(event);
+                }
+                continue;
+            }
+
+            match merge(pending, &event) {
+                Merge::Merged => return Ok(()),
+                Merge::Blocked => return Err(event),
+                Merge::Unrelated => {}
+            }
+        }
+        self.pending.push_back(event);
+        Ok(())
+    }
+
+    /// Passes all the events being held back on to `send`.
+    pub(crate) fn flush(&mut self, send: impl FnMut(Event<T>)) {
+        self.pending.drain(..).for_each(send);
+    }
+
+    /// Passes the events being held back on to `send`, until it hands one back.
+    pub(crate) fn flush_until(&mut self, mut send: impl FnMut(Event<T>) -> Result<(), Event<T>>) {
+        while let Some(event) = self.pending.pop_front() {
+            if let Err(event) = send(event) {
+                self.pending.push_front(event);
+                break;
+            }
+        }
+    }
+
+    /// Takes the oldest event being held back.
+    pub(crate) fn pop(&mut self) -> Option<Event<T>> {
+        self.pending.pop_front()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Returns whether `a` and `b` have to be kept in the order they arrived in, because they're from
+/// the same window or device, or one of them isn't from a window or device at all.
+fn ordered_against<T>(a: &Event<T>, b: &Event<T>) -> bool {
+    match (Source::of(a), Source::of(b)) {
+        (Some(a), Some(b)) => {
+            (a.window.is_some() && a.window == b.window)
+                || (a.device.is_some() && a.device == b.device)
+        }
+        _ => true,
+    }
+}
+
+/// The window and device an event came from.
+struct Source {
+    window: Option<WindowId>,
+    device: Option<DeviceId>,
+}
+
+impl Source {
+    fn of<T>(event: &Event<T>) -> Option<Self> {
+        match event {
+            Event::WindowEvent { window_id, event } => Some(Self {
+                window: Some(*window_id),
+                device: window_event_device(event),
+            }),
+            Event::DeviceEvent { device_id, .. } => Some(Self {
+                window: None,
+                device: Some(*device_id),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn window_event_device(event: &WindowEvent) -> Option<DeviceId> {
+    match event {
+        WindowEvent::KeyboardInput { device_id, .. }
+        | WindowEvent::CursorMoved { device_id, .. }
+        | WindowEvent::CursorEntered { device_id }
+        | WindowEvent::CursorLeft { device_id }
+        | WindowEvent::MouseWheel { device_id, .. }
+        | WindowEvent::MouseInput { device_id, .. }
+        | WindowEvent::TouchpadPressure { device_id, .. }
+        | WindowEvent::AxisMotion { device_id, .. } => Some(*device_id),
+        WindowEvent::Touch(touch) => Some(touch.device_id),
+        _ => None,
+    }
+}
+
+/// Merges `event` into `pending`, if they're the same kind of event from the same place.
+fn merge<T>(pending: &mut Event<T>, event: &Event<T>) -> Merge {
+    match (pending, event) {
+        (
+            Event::WindowEvent {
+                window_id: pending_window,
+                event: pending,
+            },
+            Event::WindowEvent { window_id, event },
+        ) if pending_window == window_id => merge_window_event(pending, event),
+        (
+            Event::DeviceEvent {
+                device_id: pending_device,
+                event: pending,
+            },
+            Event::DeviceEvent { device_id, event },
+        ) if pending_device == device_id => merge_device_event(pending, event),
+        _ => Merge::Unrelated,
+    }
+}
+
+fn merge_window_event(pending: &mut WindowEvent, event: &WindowEvent) -> Merge {
+    match (pending, event) {
+        (WindowEvent::Resized(pending), WindowEvent::Resized(size)) => {
+            *pending = *size;
+            Merge::Merged
+        }
+        (
+            WindowEvent::CursorMoved {
+                device_id: pending_device,
+                position: pending,
+            },
+            WindowEvent::CursorMoved {
+                device_id,
+                position,
+            },
+        ) if pending_device == device_id => {
+            *pending = *position;
+            Merge::Merged
+        }
+        (
+            WindowEvent::AxisMotion {
+                device_id: pending_device,
+                axis: pending_axis,
+                value: pending,
+            },
+            WindowEvent::AxisMotion {
+                device_id,
+                axis,
+                value,
+            },
+        ) if pending_device == device_id && pending_axis == axis => {
+            *pending = *value;
+            Merge::Merged
+        }
+        (
+            WindowEvent::MouseWheel {
+                device_id: pending_device,
+                delta: pending,
+                phase: pending_phase,
+            },
+            WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase,
+            },
+        ) if pending_device == device_id => {
+            if pending_phase == phase {
+                merge_scroll_delta(pending, delta)
+            } else {
+                Merge::Blocked
+            }
+        }
+        _ => Merge::Unrelated,
+    }
+}
+
+fn merge_device_event(pending: &mut DeviceEvent, event: &DeviceEvent) -> Merge {
+    match (pending, event) {
+        (DeviceEvent::MouseMotion { delta: pending }, DeviceEvent::MouseMotion { delta }) => {
+            pending.0 += delta.0;
+            pending.1 += delta.1;
+            Merge::Merged
+        }
+        (
+            DeviceEvent::Motion {
+                axis: pending_axis,
+                value: pending,
+            },
+            DeviceEvent::Motion { axis, value },
+        ) if pending_axis == axis => {
+            *pending += value;
+            Merge::Merged
+        }
+        (DeviceEvent::MouseWheel { delta: pending }, DeviceEvent::MouseWheel { delta }) => {
+            merge_scroll_delta(pending, delta)
+        }
+        _ => Merge::Unrelated,
+    }
+}
+
+fn merge_scroll_delta(pending: &mut MouseScrollDelta, delta: &MouseScrollDelta) -> Merge {
+    match (pending, delta) {
+        (MouseScrollDelta::LineDelta(pending_x, pending_y), MouseScrollDelta::LineDelta(x, y)) => {
+            *pending_x += x;
+            *pending_y += y;
+            Merge::Merged
+        }
+        (MouseScrollDelta::PixelDelta(pending), MouseScrollDelta::PixelDelta(delta)) => {
+            pending.x += delta.x;
+            pending.y += delta.y;
+            Merge::Merged
+        }
+        _ => Merge::Blocked,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use winit::dpi::PhysicalPosition;
+    use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta};
+
+    use super::{Coalesce, Coalescer};
+    use crate::backend::fake::{device_id, window_id, FakeEventLoop};
+    use crate::event::{Event, WindowEvent};
+    use crate::Runtime;
+
+    fn cursor_moved(x: f64) -> Event<u32> {
+        window_event(WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: PhysicalPosition::new(x, 0.0),
+        })
+    }
+
+    fn mouse_motion(delta: (f64, f64)) -> Event<u32> {
+        Event::DeviceEvent {
+            device_id: device_id(),
+            event: DeviceEvent::MouseMotion { delta },
+        }
+    }
+
+    fn mouse_wheel(delta: MouseScrollDelta) -> Event<u32> {
+        Event::DeviceEvent {
+            device_id: device_id(),
+            event: DeviceEvent::MouseWheel { delta },
+        }
+    }
+
+    fn motion(axis: u32, value: f64) -> Event<u32> {
+        Event::DeviceEvent {
+            device_id: device_id(),
+            event: DeviceEvent::Motion { axis, value },
+        }
+    }
+
+    fn window_event(event: WindowEvent) -> Event<u32> {
+        Event::WindowEvent {
+            window_id: window_id(),
+            event,
+        }
+    }
+
+    fn mouse_input() -> Event<u32> {
+        window_event(WindowEvent::MouseInput {
+            device_id: device_id(),
+            state: ElementState::Pressed,
+            button: MouseButton::Left,
+        })
+    }
+
+    /// Runs `events` through the runtime in one batch, returning the window and device events
+    /// the future receives.
+    fn receive(coalesce: Coalesce, events: Vec<Event<u32>>) -> Vec<Event<u32>> {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        for event in events {
+            event_loop.push_event(event);
+        }
+        event_loop.push_event(Event::UserEvent(0));
+
+        let output = Runtime::new().coalesce(coalesce).run_return(
+            &mut event_loop,
+            |_, mut events| async move {
+                let mut received = Vec::new();
+                while let Some(event) = events.next().await {
+                    match event {
+                        Event::UserEvent(_) => break,
+                        Event::WindowEvent { .. } | Event::DeviceEvent { .. } => {
+                            received.push(event)
+                        }
+                        _ => {}
+                    }
+                }
+                received
+            },
+        );
+        output.unwrap()
+    }
+
+    /// A burst of mouse movement, then a click and one more movement.
+    fn burst() -> Vec<Event<u32>> {
+        let mut events = Vec::new();
+        for x in 0..5 {
+            events.push(cursor_moved(x as f64));
+            events.push(mouse_motion((1.0, 2.0)));
+        }
+        events.push(mouse_input());
+        events.push(cursor_moved(9.0));
+        events
+    }
+
+    #[test]
+    fn nothing_is_coalesced_by_default() {
+        assert_eq!(receive(Coalesce::default(), burst()).len(), 12);
+    }
+
+    #[test]
+    fn coalesced_events_are_merged() {
+        let coalesce = Coalesce::none().cursor_moved(true).mouse_motion(true);
+        assert_eq!(
+            receive(coalesce, burst()),
+            [
+                cursor_moved(4.0),
+                mouse_motion((5.0, 10.0)),
+                mouse_input(),
+                cursor_moved(9.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn x11_mouse_movement_is_merged() {
+        // This is what winit sends for every movement of the mouse on X11, in this order.
+        let mut events = Vec::new();
+        for x in 0..10 {
+            events.push(cursor_moved(x as f64));
+            events.push(motion(0, 1.0));
+            events.push(motion(1, 2.0));
+            events.push(mouse_motion((1.0, 2.0)));
+        }
+
+        assert_eq!(
+            receive(Coalesce::all(), events),
+            [
+                cursor_moved(9.0),
+                motion(0, 10.0),
+                motion(1, 20.0),
+                mouse_motion((10.0, 20.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_related_events_send_the_coalesced_ones_first() {
+        let coalesce = Coalesce::none().mouse_motion(true);
+        let events = vec![
+            mouse_motion((1.0, 0.0)),
+            // Not from the mouse, so it doesn't need to stay after the motion.
+            window_event(WindowEvent::Focused(true)),
+            mouse_motion((1.0, 0.0)),
+            // From the mouse, so it does.
+            mouse_input(),
+            mouse_motion((1.0, 0.0)),
+        ];
+
+        assert_eq!(
+            receive(coalesce, events),
+            [
+                window_event(WindowEvent::Focused(true)),
+                mouse_motion((2.0, 0.0)),
+                mouse_input(),
+                mouse_motion((1.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn held_events_are_not_overtaken() {
+        let mut coalescer = Coalescer::new(Coalesce::none().mouse_motion(true));
+        let mut sent = Vec::new();
+        coalescer.push(mouse_motion((1.0, 0.0)), |event| sent.push(event));
+        coalescer.hold(Event::RedrawEventsCleared);
+        coalescer.push(mouse_motion((1.0, 0.0)), |event| sent.push(event));
+
+        assert_eq!(
+            sent,
+            [
+                mouse_motion((1.0, 0.0)),
+                Event::RedrawEventsCleared,
+                mouse_motion((1.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn wheel_deltas_in_different_units_stay_in_order() {
+        let mut coalescer = Coalescer::new(Coalesce::all());
+        let mut sent = Vec::new();
+        let pixels = MouseScrollDelta::PixelDelta(PhysicalPosition::new(0.0, 10.0));
+        for delta in [
+            MouseScrollDelta::LineDelta(0.0, 1.0),
+            MouseScrollDelta::LineDelta(0.0, 1.0),
+            pixels,
+            MouseScrollDelta::LineDelta(0.0, 1.0),
+        ] {
+            coalescer.push(mouse_wheel(delta), |event| sent.push(event));
+        }
+        coalescer.flush(|event| sent.push(event));
+
+        assert_eq!(
+            sent,
+            [
+                mouse_wheel(MouseScrollDelta::LineDelta(0.0, 2.0)),
+                mouse_wheel(pixels),
+                mouse_wheel(MouseScrollDelta::LineDelta(0.0, 1.0)),
+            ]
+        );
+    }
+}
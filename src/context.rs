@@ -0,0 +1,60 @@
+//! State shared between [`run`](crate::run) and the futures it drives.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::control_flow::ControlFlowState;
+use crate::exit::ExitState;
+use crate::idle::IdleState;
+use crate::task::{BlockingPool, Executor};
+use crate::time::Timers;
+use crate::window::WindowRegistry;
+
+/// The state of the runtime on the current thread.
+#[derive(Debug, Default)]
+pub(crate) struct RuntimeContext {
+    pub(crate) tasks: Executor,
+    pub(crate) blocking: BlockingPool,
+    pub(crate) control_flow: ControlFlowState,
+    pub(crate) exit: Arc<ExitState>,
+    pub(crate) idle: IdleState,
+    pub(crate) timers: RefCell<Timers>,
+    pub(crate) windows: RefCell<WindowRegistry>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<RuntimeContext>>> = const { RefCell::new(None) };
+}
+
+/// Makes `context` the current runtime context until the returned guard is dropped.
+pub(crate) fn enter(context: Rc<RuntimeContext>) -> EnterGuard {
+    let prev = CURRENT.with(|current| current.replace(Some(context)));
+    EnterGuard { prev }
+}
+
+pub(crate) struct EnterGuard {
+    prev: Option<Rc<RuntimeContext>>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+/// Calls `f` with the current runtime context.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub(crate) fn with<R>(f: impl FnOnce(&RuntimeContext) -> R) -> R {
+    try_with(f).expect("must be called from within `winit_async::run`")
+}
+
+/// Calls `f` with the current runtime context, or returns `None` if there isn't one.
+pub(crate) fn try_with<R>(f: impl FnOnce(&RuntimeContext) -> R) -> Option<R> {
+    let context = CURRENT.with(|current| current.borrow().clone())?;
+    Some(f(&context))
+}
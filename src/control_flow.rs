@@ -0,0 +1,231 @@
+//! Choosing the [`ControlFlow`] the event loop follows between polls.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use winit::event_loop::ControlFlow;
+
+use crate::context;
+
+/// Sets the [`ControlFlow`] the event loop follows once the current poll is done.
+///
+/// This defaults to [`ControlFlow::Wait`], or whatever was passed to
+/// [`Runtime::control_flow`](crate::Runtime::control_flow). The event loop still wakes up in time
+/// for any pending timers, and [`ContinuousFrames`] overrides this with [`ControlFlow::Poll`]
+/// while it's held.
+///
+/// [`ControlFlow::WaitUntil`] only lasts until its deadline: once that's passed, this goes back
+/// to [`ControlFlow::Wait`].
+///
+/// Setting [`ControlFlow::Exit`] destroys the event loop, after which the future is driven to
+/// completion as described in [`run`](crate::run).
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn set_control_flow(control_flow: ControlFlow) {
+    context::with(|context| context.control_flow.requested.set(control_flow))
+}
+
+/// Returns the [`ControlFlow`] most recently passed to [`set_control_flow`], or
+/// [`ControlFlow::Wait`] if that was a [`ControlFlow::WaitUntil`] whose deadline has passed.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn control_flow() -> ControlFlow {
+    context::with(|context| context.control_flow.requested.get())
+}
+
+/// Keeps the event loop running continuously until the returned guard is dropped, e.g. while an
+/// animation is playing.
+///
+/// While any of these guards are alive, the event loop uses [`ControlFlow::Poll`] regardless of
+/// [`set_control_flow`]. Once they've all been dropped, it goes back to what was set there.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn request_continuous_frames() -> ContinuousFrames {
+    let count = context::with(|context| context.control_flow.continuous_frames.clone());
+    count.set(count.get() + 1);
+    ContinuousFrames { count }
+}
+
+/// A guard returned by [`request_continuous_frames`], which keeps the event loop running
+/// continuously until it's dropped.
+#[derive(Debug)]
+#[must_use = "the event loop stops running continuously once this is dropped"]
+pub struct ContinuousFrames {
+    count: Rc<Cell<usize>>,
+}
+
+impl Drop for ContinuousFrames {
+    fn drop(&mut self) {
+        self.count.set(self.count.get() - 1);
+    }
+}
+
+/// The control flow requested by the futures of a runtime.
+#[derive(Debug)]
+pub(crate) struct ControlFlowState {
+    pub(crate) requested: Cell<ControlFlow>,
+    /// The number of [`ContinuousFrames`] guards currently alive.
+    continuous_frames: Rc<Cell<usize>>,
+}
+
+impl Default for ControlFlowState {
+    fn default() -> Self {
+        Self {
+            requested: Cell::new(ControlFlow::Wait),
+            continuous_frames: Rc::new(Cell::new(0)),
+        }
+    }
+}
+
+impl ControlFlowState {
+    /// Works out the control flow the event loop should follow, given the deadline of the next
+    /// timer.
+    pub(crate) fn resolve(&self, now: Instant, next_deadline: Option<Instant>) -> ControlFlow {
+        if let ControlFlow::WaitUntil(deadline) = self.requested.get() {
+            // Waiting until a time that's already passed would have the event loop wake up over
+            // and over again.
+            if deadline <= now {
+                self.requested.set(ControlFlow::Wait);
+            }
+        }
+
+        let requested = match self.requested.get() {
+            ControlFlow::Exit => return ControlFlow::Exit,
+            _ if self.continuous_frames.get() > 0 => ControlFlow::Poll,
+            requested => requested,
+        };
+
+        match (requested, next_deadline) {
+            (ControlFlow::Wait, Some(deadline)) => ControlFlow::WaitUntil(deadline),
+            (ControlFlow::WaitUntil(requested), Some(deadline)) => {
+                ControlFlow::WaitUntil(requested.min(deadline))
+            }
+            (requested, _) => requested,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures_util::StreamExt;
+    use winit::event_loop::ControlFlow;
+
+    use super::{control_flow, request_continuous_frames, set_control_flow, ControlFlowState};
+    use crate::backend::fake::FakeEventLoop;
+    use crate::event::{Event, StartCause};
+    use crate::Runtime;
+
+    #[test]
+    fn resolve() {
+        let state = ControlFlowState::default();
+        let now = Instant::now();
+        let soon = now + Duration::from_millis(10);
+        let later = now + Duration::from_millis(20);
+
+        assert_eq!(state.resolve(now, None), ControlFlow::Wait);
+        assert_eq!(state.resolve(now, Some(soon)), ControlFlow::WaitUntil(soon));
+
+        state.requested.set(ControlFlow::WaitUntil(later));
+        assert_eq!(state.resolve(now, Some(soon)), ControlFlow::WaitUntil(soon));
+        state.requested.set(ControlFlow::WaitUntil(soon));
+        assert_eq!(
+            state.resolve(now, Some(later)),
+            ControlFlow::WaitUntil(soon)
+        );
+
+        // Once the requested deadline's passed, it's forgotten about.
+        assert_eq!(state.resolve(later, None), ControlFlow::Wait);
+        assert_eq!(state.requested.get(), ControlFlow::Wait);
+
+        state.continuous_frames.set(1);
+        assert_eq!(state.resolve(now, Some(soon)), ControlFlow::Poll);
+        state.requested.set(ControlFlow::Exit);
+        assert_eq!(state.resolve(now, Some(soon)), ControlFlow::Exit);
+    }
+
+    #[test]
+    fn continuous_frames_keep_the_event_loop_running() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            let frames = request_continuous_frames();
+            let mut batches = 0;
+            while let Some(event) = events.next().await {
+                if let Event::MainEventsCleared = event {
+                    batches += 1;
+                    if batches == 5 {
+                        break;
+                    }
+                }
+            }
+            drop(frames);
+            // Without the guard, the event loop goes back to waiting, and so runs out of events.
+            while events.next().await.is_some() {}
+            batches
+        });
+
+        assert_eq!(output.unwrap(), 5);
+        let control_flows = proxy.control_flows();
+        assert!(control_flows.contains(&ControlFlow::Poll));
+        assert_eq!(control_flows.last(), Some(&ControlFlow::Wait));
+    }
+
+    #[test]
+    fn exit_destroys_the_event_loop() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+
+        let output = Runtime::new().control_flow(ControlFlow::Poll).run_return(
+            &mut event_loop,
+            |_, mut events| async move {
+                let mut batches = 0;
+                while let Some(event) = events.next().await {
+                    if let Event::MainEventsCleared = event {
+                        batches += 1;
+                        if batches == 3 {
+                            set_control_flow(ControlFlow::Exit);
+                        }
+                    }
+                }
+                batches
+            },
+        );
+
+        assert_eq!(output.unwrap(), 3);
+        assert_eq!(proxy.control_flows().last(), Some(&ControlFlow::Exit));
+    }
+
+    #[test]
+    fn wait_until_only_lasts_until_its_deadline() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            set_control_flow(ControlFlow::WaitUntil(
+                Instant::now() + Duration::from_millis(10),
+            ));
+            let mut resumes = 0;
+            // Once the deadline's been reached, the event loop goes back to waiting, and so runs
+            // out of events. Don't wait for that forever if it doesn't, though.
+            while let Some(event) = events.next().await {
+                if let Event::NewEvents(StartCause::ResumeTimeReached { .. }) = event {
+                    resumes += 1;
+                    if resumes > 1 {
+                        break;
+                    }
+                }
+            }
+            (resumes, control_flow())
+        });
+        assert_eq!(output.unwrap(), (1, ControlFlow::Wait));
+    }
+}
@@ -0,0 +1,444 @@
+//! A `'static` version of winit's [`Event`](winit::event::Event) type.
+//!
+//! winit's events borrow from the event loop in one case: `ScaleFactorChanged` hands out a
+//! `&mut` to the window's new inner size, which has to be written to before the event handler
+//! returns. That makes them impossible to send through a channel as-is, so this module mirrors
+//! them with an owned [`NewInnerSize`] slot in place of the reference.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::window::{Theme, WindowId};
+
+pub use winit::event::{
+    AxisId, ButtonId, DeviceEvent, DeviceId, ElementState, Force, KeyboardInput, ModifiersState,
+    MouseButton, MouseScrollDelta, ScanCode, StartCause, Touch, TouchPhase, VirtualKeyCode,
+};
+
+/// Describes a generic event.
+///
+/// This mirrors [`winit::event::Event`]; see there for details on each variant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<T: 'static> {
+    NewEvents(StartCause),
+    WindowEvent {
+        window_id: WindowId,
+        event: WindowEvent,
+    },
+    DeviceEvent {
+        device_id: DeviceId,
+        event: DeviceEvent,
+    },
+    UserEvent(T),
+    Suspended,
+    Resumed,
+    MainEventsCleared,
+    RedrawRequested(WindowId),
+    RedrawEventsCleared,
+    LoopDestroyed,
+}
+
+/// Describes an event from a window.
+///
+/// This mirrors [`winit::event::WindowEvent`], minus the deprecated `modifiers` fields (use
+/// [`WindowEvent::ModifiersChanged`] instead).
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowEvent {
+    Resized(PhysicalSize<u32>),
+    Moved(PhysicalPosition<i32>),
+    CloseRequested,
+    Destroyed,
+    DroppedFile(PathBuf),
+    HoveredFile(PathBuf),
+    HoveredFileCancelled,
+    ReceivedCharacter(char),
+    Focused(bool),
+    KeyboardInput {
+        device_id: DeviceId,
+        input: KeyboardInput,
+        is_synthetic: bool,
+    },
+    ModifiersChanged(ModifiersState),
+    CursorMoved {
+        device_id: DeviceId,
+        position: PhysicalPosition<f64>,
+    },
+    CursorEntered {
+        device_id: DeviceId,
+    },
+    CursorLeft {
+        device_id: DeviceId,
+    },
+    MouseWheel {
+        device_id: DeviceId,
+        delta: MouseScrollDelta,
+        phase: TouchPhase,
+    },
+    MouseInput {
+        device_id: DeviceId,
+        state: ElementState,
+        button: MouseButton,
+    },
+    TouchpadPressure {
+        device_id: DeviceId,
+        pressure: f32,
+        stage: i64,
+    },
+    AxisMotion {
+        device_id: DeviceId,
+        axis: AxisId,
+        value: f64,
+    },
+    Touch(Touch),
+    /// The window's scale factor has changed.
+    ///
+    /// The window will be resized to whatever size is in `new_inner_size` once the event has
+    /// been handled. That defaults to the size suggested by the OS, but can be changed with
+    /// [`NewInnerSize::set`].
+    ScaleFactorChanged {
+        scale_factor: f64,
+        new_inner_size: NewInnerSize,
+    },
+    ThemeChanged(Theme),
+}
+
+/// The size a window will be resized to after a [`WindowEvent::ScaleFactorChanged`].
+///
+/// This is a slot shared with the runtime, which reads it back and hands it to winit once the
+/// future has been polled with the event. So a new size only takes effect if it's set during the
+/// same poll that the event is received in; after that, setting it does nothing.
+#[derive(Debug, Clone)]
+pub struct NewInnerSize(Arc<Mutex<PhysicalSize<u32>>>);
+
+impl NewInnerSize {
+    /// Creates a new slot holding `size`.
+    ///
+    /// This is only needed when constructing events by hand, e.g. for a
+    /// [`FakeEventLoop`](crate::backend::fake::FakeEventLoop).
+    pub fn new(size: PhysicalSize<u32>) -> Self {
+        Self(Arc::new(Mutex::new(size)))
+    }
+
+    /// Returns the size the window is going to be resized to.
+    ///
+    /// Unless [`set`](Self::set) has been called, this is the size suggested by the OS.
+    pub fn get(&self) -> PhysicalSize<u32> {
+        *self.0.lock().unwrap()
+    }
+
+    /// Suggests a different size for the window to be resized to.
+    pub fn set(&self, size: PhysicalSize<u32>) {
+        *self.0.lock().unwrap() = size;
+    }
+}
+
+impl PartialEq for NewInnerSize {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+/// The reference winit wants a [`NewInnerSize`] written back to.
+pub(crate) struct SizeWriteBack<'a> {
+    slot: NewInnerSize,
+    new_inner_size: &'a mut PhysicalSize<u32>,
+}
+
+impl SizeWriteBack<'_> {
+    /// Passes whatever size is in the slot on to winit.
+    pub(crate) fn write_back(self) {
+        *self.new_inner_size = self.slot.get();
+    }
+}
+
+impl<T> Event<T> {
+    /// Maps the user event contained in this event, if there is one.
+    pub fn map_user_event<U>(self, f: impl FnOnce(T) -> U) -> Event<U> {
+        match self {
+            Event::NewEvents(cause) => Event::NewEvents(cause),
+            Event::WindowEvent { window_id, event } => Event::WindowEvent { window_id, event },
+            Event::DeviceEvent { device_id, event } => Event::DeviceEvent { device_id, event },
+            Event::UserEvent(event) => Event::UserEvent(f(event)),
+            Event::Suspended => Event::Suspended,
+            Event::Resumed => Event::Resumed,
+            Event::MainEventsCleared => Event::MainEventsCleared,
+            Event::RedrawRequested(window_id) => Event::RedrawRequested(window_id),
+            Event::RedrawEventsCleared => Event::RedrawEventsCleared,
+            Event::LoopDestroyed => Event::LoopDestroyed,
+        }
+    }
+
+    /// Clones this event, using `clone_user_event` to clone user events.
+    pub(crate) fn clone_with(&self, clone_user_event: fn(&T) -> T) -> Self {
+        match self {
+            Event::NewEvents(cause) => Event::NewEvents(*cause),
+            Event::WindowEvent { window_id, event } => Event::WindowEvent {
+                window_id: *window_id,
+                event: event.clone(),
+            },
+            Event::DeviceEvent { device_id, event } => Event::DeviceEvent {
+                device_id: *device_id,
+                event: event.clone(),
+            },
+            Event::UserEvent(event) => Event::UserEvent(clone_user_event(event)),
+            Event::Suspended => Event::Suspended,
+            Event::Resumed => Event::Resumed,
+            Event::MainEventsCleared => Event::MainEventsCleared,
+            Event::RedrawRequested(window_id) => Event::RedrawRequested(*window_id),
+            Event::RedrawEventsCleared => Event::RedrawEventsCleared,
+            Event::LoopDestroyed => Event::LoopDestroyed,
+        }
+    }
+
+    /// Converts a winit event into our own event type.
+    ///
+    /// If the event is a `ScaleFactorChanged`, this also returns what's needed to pass the new
+    /// inner size back to winit.
+    pub(crate) fn from_winit(
+        event: winit::event::Event<'_, T>,
+    ) -> (Self, Option<SizeWriteBack<'_>>) {
+        use winit::event::Event as E;
+
+        let event = match event {
+            E::NewEvents(cause) => Event::NewEvents(cause),
+            E::WindowEvent { window_id, event } => {
+                let (event, write_back) = WindowEvent::from_winit(event);
+                return (Event::WindowEvent { window_id, event }, write_back);
+            }
+            E::DeviceEvent { device_id, event } => Event::DeviceEvent { device_id, event },
+            E::UserEvent(event) => Event::UserEvent(event),
+            E::Suspended => Event::Suspended,
+            E::Resumed => Event::Resumed,
+            E::MainEventsCleared => Event::MainEventsCleared,
+            E::RedrawRequested(window_id) => Event::RedrawRequested(window_id),
+            E::RedrawEventsCleared => Event::RedrawEventsCleared,
+            E::LoopDestroyed => Event::LoopDestroyed,
+        };
+
+        (event, None)
+    }
+
+    /// Converts this back into a winit event.
+    ///
+    /// If this is a `ScaleFactorChanged`, `size` is filled in with the current contents of its
+    /// slot and lent to winit's event, and the slot is returned so that whatever winit's event
+    /// handler leaves in `size` can be put back into it.
+    pub(crate) fn into_winit(
+        self,
+        size: &mut PhysicalSize<u32>,
+    ) -> (winit::event::Event<'_, T>, Option<NewInnerSize>) {
+        use winit::event::Event as E;
+
+        let event = match self {
+            Event::NewEvents(cause) => E::NewEvents(cause),
+            Event::WindowEvent { window_id, event } => {
+                let (event, slot) = event.into_winit(size);
+                return (E::WindowEvent { window_id, event }, slot);
+            }
+            Event::DeviceEvent { device_id, event } => E::DeviceEvent { device_id, event },
+            Event::UserEvent(event) => E::UserEvent(event),
+            Event::Suspended => E::Suspended,
+            Event::Resumed => E::Resumed,
+            Event::MainEventsCleared => E::MainEventsCleared,
+            Event::RedrawRequested(window_id) => E::RedrawRequested(window_id),
+            Event::RedrawEventsCleared => E::RedrawEventsCleared,
+            Event::LoopDestroyed => E::LoopDestroyed,
+        };
+
+        (event, None)
+    }
+}
+
+impl WindowEvent {
+    fn from_winit(event: winit::event::WindowEvent<'_>) -> (Self, Option<SizeWriteBack<'_>>) {
+        use winit::event::WindowEvent as E;
+
+        let event = match event {
+            E::Resized(size) => WindowEvent::Resized(size),
+            E::Moved(position) => WindowEvent::Moved(position),
+            E::CloseRequested => WindowEvent::CloseRequested,
+            E::Destroyed => WindowEvent::Destroyed,
+            E::DroppedFile(path) => WindowEvent::DroppedFile(path),
+            E::HoveredFile(path) => WindowEvent::HoveredFile(path),
+            E::HoveredFileCancelled => WindowEvent::HoveredFileCancelled,
+            E::ReceivedCharacter(c) => WindowEvent::ReceivedCharacter(c),
+            E::Focused(focused) => WindowEvent::Focused(focused),
+            E::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic,
+            } => WindowEvent::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic,
+            },
+            E::ModifiersChanged(modifiers) => WindowEvent::ModifiersChanged(modifiers),
+            E::CursorMoved {
+                device_id,
+                position,
+                ..
+            } => WindowEvent::CursorMoved {
+                device_id,
+                position,
+            },
+            E::CursorEntered { device_id } => WindowEvent::CursorEntered { device_id },
+            E::CursorLeft { device_id } => WindowEvent::CursorLeft { device_id },
+            E::MouseWheel {
+                device_id,
+                delta,
+                phase,
+                ..
+            } => WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase,
+            },
+            E::MouseInput {
+                device_id,
+                state,
+                button,
+                ..
+            } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+            },
+            E::TouchpadPressure {
+                device_id,
+                pressure,
+                stage,
+            } => WindowEvent::TouchpadPressure {
+                device_id,
+                pressure,
+                stage,
+            },
+            E::AxisMotion {
+                device_id,
+                axis,
+                value,
+            } => WindowEvent::AxisMotion {
+                device_id,
+                axis,
+                value,
+            },
+            E::Touch(touch) => WindowEvent::Touch(touch),
+            E::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                let slot = NewInnerSize::new(*new_inner_size);
+                let event = WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size: slot.clone(),
+                };
+                return (
+                    event,
+                    Some(SizeWriteBack {
+                        slot,
+                        new_inner_size,
+                    }),
+                );
+            }
+            E::ThemeChanged(theme) => WindowEvent::ThemeChanged(theme),
+        };
+
+        (event, None)
+    }
+
+    #[allow(deprecated)]
+    fn into_winit(
+        self,
+        size: &mut PhysicalSize<u32>,
+    ) -> (winit::event::WindowEvent<'_>, Option<NewInnerSize>) {
+        use winit::event::WindowEvent as E;
+
+        // winit still wants these filled in, even though they're deprecated.
+        let modifiers = ModifiersState::empty();
+
+        let event = match self {
+            WindowEvent::Resized(size) => E::Resized(size),
+            WindowEvent::Moved(position) => E::Moved(position),
+            WindowEvent::CloseRequested => E::CloseRequested,
+            WindowEvent::Destroyed => E::Destroyed,
+            WindowEvent::DroppedFile(path) => E::DroppedFile(path),
+            WindowEvent::HoveredFile(path) => E::HoveredFile(path),
+            WindowEvent::HoveredFileCancelled => E::HoveredFileCancelled,
+            WindowEvent::ReceivedCharacter(c) => E::ReceivedCharacter(c),
+            WindowEvent::Focused(focused) => E::Focused(focused),
+            WindowEvent::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic,
+            } => E::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic,
+            },
+            WindowEvent::ModifiersChanged(modifiers) => E::ModifiersChanged(modifiers),
+            WindowEvent::CursorMoved {
+                device_id,
+                position,
+            } => E::CursorMoved {
+                device_id,
+                position,
+                modifiers,
+            },
+            WindowEvent::CursorEntered { device_id } => E::CursorEntered { device_id },
+            WindowEvent::CursorLeft { device_id } => E::CursorLeft { device_id },
+            WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase,
+            } => E::MouseWheel {
+                device_id,
+                delta,
+                phase,
+                modifiers,
+            },
+            WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+            } => E::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            },
+            WindowEvent::TouchpadPressure {
+                device_id,
+                pressure,
+                stage,
+            } => E::TouchpadPressure {
+                device_id,
+                pressure,
+                stage,
+            },
+            WindowEvent::AxisMotion {
+                device_id,
+                axis,
+                value,
+            } => E::AxisMotion {
+                device_id,
+                axis,
+                value,
+            },
+            WindowEvent::Touch(touch) => E::Touch(touch),
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                *size = new_inner_size.get();
+                let event = E::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size: size,
+                };
+                return (event, Some(new_inner_size));
+            }
+            WindowEvent::ThemeChanged(theme) => E::ThemeChanged(theme),
+        };
+
+        (event, None)
+    }
+}
@@ -0,0 +1,192 @@
+//! Exiting the event loop from anywhere, not just by completing the future passed to
+//! [`run`](crate::run).
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+
+use crate::context;
+
+/// Returns a handle for exiting the event loop.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn exit_handle() -> ExitHandle {
+    context::with(|context| ExitHandle {
+        state: context.exit.clone(),
+    })
+}
+
+/// A handle for exiting the event loop, returned by [`exit_handle`].
+///
+/// This can be sent to other threads, and used after the task which created it has finished.
+///
+/// Exiting stops the event loop without waiting for the future passed to [`run`](crate::run) to
+/// complete. Once the event loop gets around to it:
+///
+/// 1. Every task spawned with [`spawn_local`](crate::spawn_local) is cancelled, in the order
+///    they were spawned.
+/// 2. The future passed to [`run`](crate::run) is cancelled.
+/// 3. The event loop is destroyed, and [`run`](crate::run) or [`run_return`](crate::run_return)
+///    finishes up as usual.
+///
+/// Since the future never completes, [`run_return`](crate::run_return) returns [`Exited`]
+/// instead of its output.
+#[derive(Debug, Clone)]
+pub struct ExitHandle {
+    state: Arc<ExitState>,
+}
+
+impl ExitHandle {
+    /// Exits the event loop, with an exit code of 0.
+    pub fn exit(&self) {
+        self.exit_with_code(0)
+    }
+
+    /// Exits the event loop with the given exit code.
+    ///
+    /// winit doesn't support exit codes itself, so once the event loop has been destroyed,
+    /// [`run`](crate::run) exits the process with this code (if it isn't 0) using
+    /// [`process::exit`](std::process::exit). [`run_return`](crate::run_return) just returns it.
+    ///
+    /// If the event loop has already been asked to exit, this does nothing: the first exit code
+    /// wins.
+    pub fn exit_with_code(&self, code: i32) {
+        let mut requested = self.state.code.lock().unwrap();
+        if requested.is_some() {
+            return;
+        }
+        *requested = Some(code);
+        drop(requested);
+
+        if let Some(waker) = &*self.state.waker.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// The error returned by [`run_return`](crate::run_return) when the event loop is exited through
+/// an [`ExitHandle`] before the future completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exited {
+    code: i32,
+}
+
+impl Exited {
+    pub(crate) fn new(code: i32) -> Self {
+        Self { code }
+    }
+
+    /// Returns the exit code passed to [`ExitHandle::exit_with_code`], or 0 if
+    /// [`ExitHandle::exit`] was used.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+}
+
+impl fmt::Display for Exited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the event loop was exited with code {}", self.code)
+    }
+}
+
+impl Error for Exited {}
+
+/// Shared between a runtime and its [`ExitHandle`]s.
+#[derive(Debug, Default)]
+pub(crate) struct ExitState {
+    code: Mutex<Option<i32>>,
+    /// Woken when an exit is requested, to get the event loop to notice.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ExitState {
+    pub(crate) fn set_waker(&self, waker: Waker) {
+        *self.waker.lock().unwrap() = Some(waker);
+    }
+
+    /// Returns the exit code, if an exit has been requested.
+    pub(crate) fn requested(&self) -> Option<i32> {
+        *self.code.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::future;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use winit::event_loop::ControlFlow;
+
+    use super::exit_handle;
+    use crate::backend::fake::FakeEventLoop;
+    use crate::spawn_local;
+
+    /// Records its name in a shared log when it's dropped.
+    struct Dropped(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    #[test]
+    fn exiting_cancels_tasks_before_the_future() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let output = crate::run_return(&mut event_loop, {
+            let log = log.clone();
+            |_, _| async move {
+                let _root = Dropped("root", log.clone());
+                for name in ["a", "b"] {
+                    let dropped = Dropped(name, log.clone());
+                    spawn_local(async move {
+                        let _dropped = dropped;
+                        future::pending::<()>().await
+                    })
+                    .detach();
+                }
+                let handle = exit_handle();
+                spawn_local(async move { handle.exit_with_code(3) }).detach();
+                future::pending::<()>().await
+            }
+        });
+
+        assert_eq!(output.unwrap_err().code(), 3);
+        assert_eq!(*log.borrow(), ["a", "b", "root"]);
+        assert_eq!(proxy.control_flows().last(), Some(&ControlFlow::Exit));
+    }
+
+    #[test]
+    fn exiting_from_another_thread() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let output = crate::run_return(&mut event_loop, |_, _| async move {
+            let handle = exit_handle();
+            thread::spawn(move || handle.exit());
+            future::pending::<()>().await
+        });
+        assert_eq!(output.unwrap_err().code(), 0);
+    }
+
+    #[test]
+    fn the_first_exit_code_wins() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async move {
+            let handle = exit_handle();
+            handle.exit_with_code(1);
+            handle.exit_with_code(2);
+            future::pending::<()>().await
+        });
+        assert_eq!(output.unwrap_err().code(), 1);
+    }
+}
@@ -0,0 +1,228 @@
+//! Waiting for the event loop to run out of things to do.
+
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::context;
+
+/// Waits until the event loop is next idle.
+///
+/// This resolves at the next [`Event::MainEventsCleared`](crate::event::Event::MainEventsCleared)
+/// by which point the future passed to [`run`](crate::run) and every task which has been woken
+/// have all run, and none of them have been woken again. That makes it a good place for deferred
+/// work like autosaving, which shouldn't hold up responding to input.
+///
+/// If this starts waiting while the event loop is between batches of events, the event loop is
+/// woken up to run another one; otherwise, it's left to finish the batch it's in. Anything which
+/// starts waiting after the event loop's already gone idle in a batch waits for the next batch,
+/// so waiting on this in a loop doesn't keep the event loop busy.
+///
+/// Once the event loop has been destroyed, this resolves whenever nothing else is ready to run.
+///
+/// The returned future must be polled from within [`run`](crate::run).
+pub fn idle() -> Idle {
+    Idle { registration: None }
+}
+
+/// A future returned by [`idle`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Idle {
+    /// The runtime's idle generation when this was first polled, and the index of its waker in
+    /// the runtime's [`IdleState`].
+    registration: Option<(u64, usize)>,
+}
+
+impl Future for Idle {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        context::with(|context| {
+            let state = &context.idle;
+            let current = state.generation.get();
+            match self.registration {
+                Some((generation, _)) if current > generation => Poll::Ready(()),
+                Some((_, index)) => {
+                    let mut waiters = state.waiters.borrow_mut();
+                    match &waiters[index] {
+                        Some(waker) if waker.will_wake(cx.waker()) => {}
+                        _ => waiters[index] = Some(cx.waker().clone()),
+                    }
+                    Poll::Pending
+                }
+                None => {
+                    let mut waiters = state.waiters.borrow_mut();
+                    self.registration = Some((current, waiters.len()));
+                    waiters.push(Some(cx.waker().clone()));
+                    state.registered.set(true);
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Idle {
+    fn drop(&mut self) {
+        if let Some((generation, index)) = self.registration {
+            context::try_with(|context| {
+                let state = &context.idle;
+                if state.generation.get() == generation {
+                    state.waiters.borrow_mut()[index] = None;
+                }
+            });
+        }
+    }
+}
+
+/// The futures of a runtime which are waiting for it to go idle.
+#[derive(Debug, Default)]
+pub(crate) struct IdleState {
+    /// Incremented every time the runtime goes idle, so that [`Idle`] futures can tell whether
+    /// it's happened since they started waiting.
+    generation: Cell<u64>,
+    /// The wakers of the [`Idle`] futures waiting for the current generation, which are `None`
+    /// once they've been dropped.
+    waiters: RefCell<Vec<Option<Waker>>>,
+    /// Whether a new [`Idle`] future has started waiting since this was last checked.
+    registered: Cell<bool>,
+}
+
+impl IdleState {
+    /// Returns whether anything is waiting for the runtime to go idle.
+    pub(crate) fn is_awaited(&self) -> bool {
+        self.waiters.borrow().iter().any(Option::is_some)
+    }
+
+    /// Returns whether a new [`Idle`] future has started waiting since the last time this was
+    /// called.
+    pub(crate) fn take_registered(&self) -> bool {
+        self.registered.take()
+    }
+
+    /// Marks the runtime as having gone idle, and wakes everything waiting for it.
+    pub(crate) fn wake_all(&self) {
+        self.generation.set(self.generation.get() + 1);
+        let waiters = self.waiters.take();
+        for waker in waiters.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures_util::{FutureExt, StreamExt};
+
+    use super::idle;
+    use crate::backend::fake::{self, FakeEventLoop};
+    use crate::context;
+    use crate::event::Event;
+
+    #[test]
+    fn resolves_once_woken_tasks_have_run() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let task_log = log.clone();
+            crate::spawn_local(async move {
+                for i in 0..3 {
+                    task_log.borrow_mut().push(i);
+                    yield_now().await;
+                }
+            })
+            .detach();
+
+            idle().await;
+            log.borrow_mut().push(100);
+            let output = log.borrow().clone();
+            output
+        });
+        assert_eq!(output.unwrap(), [0, 1, 2, 100]);
+    }
+
+    #[test]
+    fn waiting_in_a_loop_does_not_keep_the_event_loop_busy() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async {
+            for _ in 0..100 {
+                idle().await;
+            }
+        });
+        output.unwrap();
+        // Once the first batch goes idle, the rest are left for after the event loop's gone, so
+        // the only events are that batch's and `LoopDestroyed`.
+        assert_eq!(proxy.control_flows().len(), 4);
+    }
+
+    #[test]
+    fn starts_a_batch_for_waiters_between_batches() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+        // This gets polled for after the end of the batch.
+        event_loop.push_event(Event::RedrawRequested(fake::window_id()));
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            while let Some(event) = events.next().await {
+                if let Event::RedrawRequested(_) = event {
+                    break;
+                }
+            }
+            // Nothing else is going to come along to end a batch.
+            idle().await;
+        });
+        output.unwrap();
+    }
+
+    #[test]
+    fn resolves_after_the_event_loop_is_destroyed() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            while events.next().await.is_some() {}
+            idle().await;
+            idle().await;
+            true
+        });
+        assert!(output.unwrap());
+    }
+
+    #[test]
+    fn keeps_one_registration_per_future() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async {
+            let waiters = || context::with(|context| context.idle.waiters.borrow().clone());
+
+            let mut idle = idle();
+            for _ in 0..3 {
+                assert!((&mut idle).now_or_never().is_none());
+            }
+            let registered = waiters();
+            drop(idle);
+            (registered.len(), waiters().iter().any(Option::is_some))
+        });
+        assert_eq!(output.unwrap(), (1, false));
+    }
+
+    async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(move |cx| {
+            if yielded {
+                return std::task::Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        })
+        .await
+    }
+}
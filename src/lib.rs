@@ -1,131 +1,231 @@
+pub mod backend;
+mod broadcast;
+mod coalesce;
+mod context;
+mod control_flow;
+pub mod event;
+mod exit;
+mod idle;
+mod main_thread;
+mod oneshot;
+mod runtime;
+mod target;
+pub mod task;
+pub mod time;
+#[cfg(feature = "tokio")]
+mod tokio_bridge;
+mod waker;
+pub mod window;
+
+pub use broadcast::LagPolicy;
+pub use coalesce::Coalesce;
+pub use control_flow::{
+    control_flow, request_continuous_frames, set_control_flow, ContinuousFrames,
+};
+pub use exit::{exit_handle, ExitHandle, Exited};
+pub use idle::{idle, Idle};
+pub use main_thread::{MainThreadHandle, RunOnMain};
+pub use runtime::{PollMode, Propagation, Runtime};
+pub use target::{CreateWindow, Target};
+pub use task::{spawn_blocking, spawn_local, JoinHandle};
+pub use time::{interval, sleep, sleep_until};
+pub use window::{next_frame, window_frames};
+
 use std::future::Future;
-use std::mem;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, TryLockError};
-use std::task::{Context, Poll, Wake, Waker};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
-use async_channel::{Receiver, Sender, TrySendError};
+use async_channel::Receiver;
 use futures_core::Stream;
-use winit::event::Event;
-use winit::event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopWindowTarget};
-
+use winit::window::WindowId;
+
+use crate::backend::{Backend, RunReturn};
+use crate::broadcast::{Broadcast, Subscription};
+use crate::coalesce::Coalescer;
+use crate::event::Event;
+use crate::window::WindowEvents;
+
+/// The user event type of an [`EventLoop`](winit::event_loop::EventLoop) driven by [`run`].
+///
+/// This wraps the application's own user events alongside the wake-ups `run` uses
+/// internally, so that the two can never be mistaken for one another. Use
+/// `UserEvent::from` (or `.into()`) to send an event through an
+/// [`EventLoopProxy`](winit::event_loop::EventLoopProxy).
 #[derive(Debug)]
-pub struct Events(Receiver<Event<'static, ()>>);
-
-impl Stream for Events {
-    type Item = Event<'static, ()>;
+pub struct UserEvent<T = ()>(Message<T>);
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.0).poll_next(cx)
-    }
+#[derive(Debug)]
+enum Message<T> {
+    /// Sent by the waker to get the future polled again.
+    Wake,
+    User(T),
+}
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+impl<T> UserEvent<T> {
+    /// Returns the application's event, or `None` if this is an internal wake-up.
+    pub fn into_inner(self) -> Option<T> {
+        match self.0 {
+            Message::Wake => None,
+            Message::User(event) => Some(event),
+        }
     }
 }
 
-// TODO: support user events (implementation isn't hard, API is just annoying).
-pub fn run<F, Fut>(event_loop: EventLoop<()>, callback: F)
-where
-    F: 'static + FnOnce(&'static EventLoopWindowTarget<()>, Events) -> Fut,
-    Fut: Future<Output = ()> + 'static,
-{
-    enum State<F, Fut> {
-        Init(F),
-        Running(Fut, Sender<Event<'static, ()>>),
-        Done,
+impl<T> From<T> for UserEvent<T> {
+    fn from(event: T) -> Self {
+        Self(Message::User(event))
     }
+}
 
-    let mut state = State::Init(callback);
-
-    // A boolean shared between here and the wakers which is set to `true` if we're
-    // about to poll the future anyway, in which case the wakers do nothing.
-    let will_poll = Arc::new(AtomicBool::new(false));
-
-    let waker = create_waker(&event_loop, will_poll.clone());
-
-    event_loop.run(move |event, target, control_flow| {
-        *control_flow = ControlFlow::Wait;
-        will_poll.store(true, Ordering::Relaxed);
+/// A stream of the events received by the event loop.
+///
+/// The stream ends after yielding [`Event::LoopDestroyed`].
+#[derive(Debug)]
+pub struct Events<T: 'static = ()> {
+    rx: Receiver<Event<T>>,
+    /// Where events end up once `rx` is full under [`LagPolicy::Coalesce`].
+    overflow: Option<Arc<Mutex<Coalescer<T>>>>,
+    dropped: Arc<AtomicU64>,
+    broadcast: Arc<Mutex<Broadcast<T>>>,
+}
 
-        if matches!(state, State::Init(_)) {
-            let callback = match mem::replace(&mut state, State::Done) {
-                State::Init(callback) => callback,
-                _ => unreachable!(),
-            };
-            let (tx, rx) = async_channel::unbounded();
-            state = State::Running(Box::pin(callback(target, Events(rx))), tx);
+impl<T: 'static> Events<T> {
+    fn new(
+        broadcast: Arc<Mutex<Broadcast<T>>>,
+        Subscription {
+            rx,
+            overflow,
+            dropped,
+        }: Subscription<T>,
+    ) -> Self {
+        Self {
+            rx,
+            overflow,
+            dropped,
+            broadcast,
         }
+    }
 
-        let (future, tx) = match &mut state {
-            State::Init(_) => unreachable!(),
-            State::Running(future, tx) => (future.as_mut(), tx),
-            State::Done => return,
-        };
-
-        if event != Event::UserEvent(()) {
-            // TODO: end the stream on `LoopDestroyed`
-
-            // TODO: define our own `'static` event type which doesn't have the
-            // instant-resizing feature of `ScaleFactorChanged`
-            match tx.try_send(event.to_static().unwrap()) {
-                Ok(_) => {}
-                // We don't care if they've stopped listening for events, just ignore it.
-                Err(TrySendError::Closed(_)) => {}
-                // This should be impossible.
-                Err(TrySendError::Full(_)) => unreachable!("channel is unbounded"),
-            }
-        }
+    /// Returns a new stream which receives its own copy of every event from now on.
+    ///
+    /// Each stream buffers events separately, so one falling behind doesn't hold up the others.
+    pub fn subscribe(&self) -> Self
+    where
+        T: Clone,
+    {
+        self.subscribe_with(LagPolicy::Unbounded)
+    }
 
-        // Set this to false right before polling the future because we want any wakes
-        // inside of the poll to go through.
-        will_poll.store(false, Ordering::Relaxed);
+    /// Like [`subscribe`](Self::subscribe), but with a custom policy for what to do when the new
+    /// stream falls behind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy's capacity is 0.
+    pub fn subscribe_with(&self, policy: LagPolicy) -> Self
+    where
+        T: Clone,
+    {
+        policy.validate();
+        let subscription = self.broadcast.lock().unwrap().subscribe_cloned(policy);
+        Self::new(self.broadcast.clone(), subscription)
+    }
 
-        match future.poll(&mut Context::from_waker(&waker)) {
-            Poll::Ready(()) => {
-                *control_flow = ControlFlow::Exit;
-                state = State::Done;
-            }
-            Poll::Pending => {}
-        }
-    });
-}
+    /// Returns how many events have been dropped from this stream because it fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 
-fn create_waker(event_loop: &EventLoop<()>, will_poll: Arc<AtomicBool>) -> Waker {
-    struct ProxyWaker {
-        proxy: Mutex<EventLoopProxy<()>>,
-        will_poll: Arc<AtomicBool>,
+    /// Returns a stream of the events received by the window with the given ID.
+    ///
+    /// The stream receives events independently of this one, so events for that window are
+    /// still delivered here as well.
+    pub fn for_window(&self, window_id: WindowId) -> WindowEvents {
+        context::with(|context| context.windows.borrow_mut().subscribe(window_id))
     }
+}
 
-    impl Wake for ProxyWaker {
-        fn wake(self: Arc<Self>) {
-            self.wake_by_ref()
-        }
+impl<T: 'static> Stream for Events<T> {
+    type Item = Event<T>;
 
-        fn wake_by_ref(self: &Arc<Self>) {
-            if self.will_poll.load(Ordering::Relaxed) {
-                // The event loop is already running, no need to poll it.
-                return;
-            }
-
-            match self.proxy.try_lock() {
-                Ok(proxy) => {
-                    // Note: this only returns an error if the event loop is closed, in which case
-                    // we don't have to do anything anyway because there's nothing to wake.
-                    let _ = proxy.send_event(());
-                }
-                // If it's already locked just return, since the other holder of the lock is going
-                // to wake the event loop anyway.
-                Err(TryLockError::WouldBlock) => {}
-                Err(TryLockError::Poisoned(e)) => panic!("mutex poisoned: {e}"),
-            }
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.rx).poll_next(cx) {
+            Poll::Ready(Some(event)) => Poll::Ready(Some(event)),
+            // Anything that overflowed comes after everything in the channel.
+            poll => match self.overflow.as_ref().and_then(|o| o.lock().unwrap().pop()) {
+                Some(event) => Poll::Ready(Some(event)),
+                None => poll,
+            },
         }
     }
 
-    Arc::new(ProxyWaker {
-        proxy: Mutex::new(event_loop.create_proxy()),
-        will_poll,
-    })
-    .into()
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let overflow = self
+            .overflow
+            .as_ref()
+            .map_or(0, |o| o.lock().unwrap().len());
+        let (lower, upper) = self.rx.size_hint();
+        (lower + overflow, upper.map(|upper| upper + overflow))
+    }
+}
+
+/// Runs the event loop, driving the future returned by `callback` as events come in.
+///
+/// This is usually called with a winit [`EventLoop`](winit::event_loop::EventLoop), but any
+/// [`Backend`] will do.
+///
+/// The loop exits once the future completes, or when it's asked to through an [`ExitHandle`]
+/// (which cancels the future). If the loop gets destroyed some other way first, the future's
+/// [`Events`] stream ends and the future is driven to completion on the spot, so it can still run
+/// any cleanup code it needs to (e.g. saving settings) after it stops receiving events. Because
+/// the event loop is gone by then, this blocks the thread until the future completes.
+///
+/// The future is polled once per batch of events, rather than after every event; use
+/// [`Runtime`] to change that.
+///
+/// # Panics
+///
+/// If the future or a task spawned with [`spawn_local`] panics, the panic is caught before it
+/// can unwind through the event loop, and the loop exits. Once it's been destroyed, the panic is
+/// resumed with its original payload, unwinding out of `run`.
+///
+/// On platforms where winit supports [`EventLoopExtRunReturn`], a winit event loop is run
+/// through that, so the panic is resumed after the event loop has returned. Elsewhere, winit's
+/// `run` never returns, so it's resumed in the event loop's final callback instead: whether it
+/// then unwinds out of `run` or aborts the process is up to the platform.
+///
+/// [`EventLoopExtRunReturn`]: winit::platform::run_return::EventLoopExtRunReturn
+pub fn run<B, F, Fut>(event_loop: B, callback: F)
+where
+    B: Backend,
+    F: 'static + FnOnce(Target<B::Target>, Events<B::UserEvent>) -> Fut,
+    Fut: Future<Output = ()> + 'static,
+{
+    Runtime::new().run(event_loop, callback)
+}
+
+/// Like [`run`], but returns control to the caller once the future completes, along with the
+/// future's output.
+///
+/// If the event loop is exited through an [`ExitHandle`] before the future completes, this
+/// returns [`Exited`] instead.
+///
+/// This is built on winit's [`EventLoopExtRunReturn`], and so has the same caveats: see its
+/// documentation for details.
+///
+/// # Panics
+///
+/// If the future or a task spawned with [`spawn_local`] panics, the loop exits and the panic is
+/// resumed with its original payload once `run_return` has returned from the event loop.
+///
+/// [`EventLoopExtRunReturn`]: winit::platform::run_return::EventLoopExtRunReturn
+pub fn run_return<B, F, Fut>(event_loop: &mut B, callback: F) -> Result<Fut::Output, Exited>
+where
+    B: RunReturn,
+    F: FnOnce(Target<B::Target>, Events<B::UserEvent>) -> Fut,
+    Fut: Future,
+{
+    Runtime::new().run_return(event_loop, callback)
 }
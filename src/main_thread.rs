@@ -0,0 +1,263 @@
+//! Getting things done on the event loop thread from other threads.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use winit::error::OsError;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::{Window, WindowBuilder};
+
+use crate::oneshot;
+
+/// A handle for running code on the event loop thread, which can be sent to other threads.
+///
+/// Use [`Target::main_thread_handle`](crate::Target::main_thread_handle) to get one.
+pub struct MainThreadHandle<W: 'static> {
+    queue: Arc<JobQueue<W>>,
+}
+
+type Job<W> = Box<dyn FnOnce(&W) + Send>;
+
+impl<W> MainThreadHandle<W> {
+    pub(crate) fn new(queue: Arc<JobQueue<W>>) -> Self {
+        Self { queue }
+    }
+
+    /// Runs `f` on the event loop thread with the window target, returning a future which
+    /// resolves to its result.
+    ///
+    /// This is useful for the many winit and platform APIs which only work on the main thread.
+    /// `f` runs the next time the event loop dispatches an event, which sending it over makes
+    /// happen straight away.
+    ///
+    /// # Panics
+    ///
+    /// The returned future panics if the event loop exits before running `f`. If `f` panics, the
+    /// panic is resumed by the returned future rather than on the event loop thread.
+    pub fn run_on_main<R, F>(&self, f: F) -> RunOnMain<R>
+    where
+        F: FnOnce(&W) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        // Any panic gets sent back to whoever's waiting on the result, rather than bringing down
+        // the event loop.
+        self.queue
+            .push(Box::new(move |target| tx.run(|| f(target))));
+        RunOnMain { rx }
+    }
+}
+
+impl<T: 'static> MainThreadHandle<EventLoopWindowTarget<T>> {
+    /// Creates a new window on the event loop thread, from the [`WindowBuilder`] returned by
+    /// `builder`.
+    ///
+    /// [`WindowBuilder`] isn't [`Send`] on every platform, so rather than sending one over, this
+    /// sends over `builder` to create it on the event loop thread.
+    ///
+    /// # Panics
+    ///
+    /// The returned future panics if the event loop exits before the window gets created.
+    pub fn create_window<F>(&self, builder: F) -> RunOnMain<Result<Window, OsError>>
+    where
+        F: FnOnce() -> WindowBuilder + Send + 'static,
+    {
+        self.run_on_main(move |target| builder().build(target))
+    }
+}
+
+impl<W> Clone for MainThreadHandle<W> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<W> fmt::Debug for MainThreadHandle<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MainThreadHandle").finish_non_exhaustive()
+    }
+}
+
+/// A future returned by [`MainThreadHandle`]'s methods, which resolves once the event loop has
+/// run the requested code.
+///
+/// # Panics
+///
+/// Panics if the event loop exits before running the code, or if the code itself panicked (in
+/// which case the panic is resumed here).
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RunOnMain<R> {
+    rx: oneshot::Receiver<R>,
+}
+
+impl<R> Future for RunOnMain<R> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|output| output.expect("the event loop exited before running the code sent to it"))
+    }
+}
+
+/// The code waiting to be run on the event loop thread.
+pub(crate) struct JobQueue<W> {
+    jobs: Mutex<Jobs<W>>,
+    /// Woken whenever a job is pushed, to get the event loop to run it.
+    waker: Mutex<Option<Waker>>,
+}
+
+struct Jobs<W> {
+    queue: VecDeque<Job<W>>,
+    /// Set once the event loop's gone, after which jobs are dropped straight away.
+    closed: bool,
+}
+
+impl<W> JobQueue<W> {
+    pub(crate) fn new() -> Self {
+        Self {
+            jobs: Mutex::new(Jobs {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            waker: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn set_waker(&self, waker: Waker) {
+        *self.waker.lock().unwrap() = Some(waker);
+    }
+
+    fn push(&self, job: Job<W>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.closed {
+            return;
+        }
+        jobs.queue.push_back(job);
+        drop(jobs);
+
+        if let Some(waker) = &*self.waker.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Runs all the jobs which have been pushed so far.
+    pub(crate) fn run(&self, target: &W) {
+        let jobs = mem::take(&mut self.jobs.lock().unwrap().queue);
+        for job in jobs {
+            job(target);
+        }
+    }
+
+    /// Drops all the pending jobs, and any pushed from now on.
+    pub(crate) fn close(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.closed = true;
+        let queue = mem::take(&mut jobs.queue);
+        drop(jobs);
+        drop(queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    use crate::backend::fake::FakeEventLoop;
+    use crate::{PollMode, Runtime};
+
+    /// A minimal executor for the other side of a `MainThreadHandle`.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct ThreadWaker(Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark()
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn runs_code_sent_from_other_threads() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+        let main = thread::current().id();
+
+        let output = crate::run_return(&mut event_loop, |target, _| async move {
+            let handle = target.main_thread_handle();
+            let (tx, rx) = async_channel::bounded(1);
+            thread::spawn(move || {
+                let id = block_on(handle.run_on_main(|_| thread::current().id()));
+                tx.try_send(id).unwrap();
+            });
+            rx.recv().await.unwrap()
+        });
+        assert_eq!(output.unwrap(), main);
+    }
+
+    #[test]
+    fn resumes_panics_on_the_calling_thread() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let panicked = crate::run_return(&mut event_loop, |target, _| async move {
+            let handle = target.main_thread_handle();
+            let (tx, rx) = async_channel::bounded(1);
+            thread::spawn(move || {
+                let result = std::panic::catch_unwind(|| {
+                    block_on(handle.run_on_main(|_| -> () { panic!("oops") }))
+                });
+                tx.try_send(result.is_err()).unwrap();
+            });
+            rx.recv().await.unwrap()
+        });
+        assert!(panicked.unwrap());
+    }
+
+    #[test]
+    fn jobs_sent_while_dispatching_are_not_lost() {
+        // Pushing a job while the event loop is partway through dispatching an event used to get
+        // lost, leaving the event loop waiting forever.
+        for poll_mode in [PollMode::Batched, PollMode::Immediate] {
+            let mut event_loop =
+                FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+            let output = Runtime::new().poll_mode(poll_mode).run_return(
+                &mut event_loop,
+                |target, _| async move {
+                    let handle = target.main_thread_handle();
+                    let (tx, rx) = async_channel::bounded(1);
+                    thread::spawn(move || {
+                        let sum: u32 = (0..2000)
+                            .map(|i| block_on(handle.run_on_main(move |_| i % 2)))
+                            .sum();
+                        tx.try_send(sum).unwrap();
+                    });
+                    rx.recv().await.unwrap()
+                },
+            );
+            assert_eq!(output.unwrap(), 1000);
+        }
+    }
+}
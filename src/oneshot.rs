@@ -0,0 +1,57 @@
+//! Sending the result of running a closure on another thread back to whoever's waiting on it.
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+type Payload<R> = Result<R, Box<dyn Any + Send>>;
+
+/// Creates a channel for sending the result of a single closure.
+pub(crate) fn channel<R>() -> (Sender<R>, Receiver<R>) {
+    let (tx, rx) = async_channel::bounded(1);
+    (Sender { tx }, Receiver { rx })
+}
+
+/// The sending half of a [`channel`], which runs the closure.
+#[derive(Debug)]
+pub(crate) struct Sender<R> {
+    tx: async_channel::Sender<Payload<R>>,
+}
+
+impl<R> Sender<R> {
+    /// Runs `f` and sends its result.
+    ///
+    /// If `f` panics, the panic is sent instead, to be resumed by the [`Receiver`] rather than
+    /// on this thread.
+    pub(crate) fn run(self, f: impl FnOnce() -> R) {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        // Nobody might be waiting for the result anymore, which is fine.
+        let _ = self.tx.try_send(result);
+    }
+}
+
+/// The receiving half of a [`channel`], which resolves to the closure's result, or `None` if the
+/// [`Sender`] was dropped without running it.
+///
+/// This resumes the closure's panic if it panicked.
+#[derive(Debug)]
+pub(crate) struct Receiver<R> {
+    rx: async_channel::Receiver<Payload<R>>,
+}
+
+impl<R> Future for Receiver<R> {
+    type Output = Option<R>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<R>> {
+        match Pin::new(&mut self.rx).poll_next(cx) {
+            Poll::Ready(Some(Ok(output))) => Poll::Ready(Some(output)),
+            Poll::Ready(Some(Err(payload))) => panic::resume_unwind(payload),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
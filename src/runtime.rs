@@ -0,0 +1,989 @@
+//! The event handler which drives the future passed to [`run`](crate::run) and friends, and the
+//! [`Runtime`] builder for configuring it.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+use winit::event_loop::{ControlFlow, EventLoopWindowTarget};
+
+use crate::backend::{Backend, RunReturn};
+use crate::broadcast::Broadcast;
+use crate::coalesce::{Coalesce, Coalescer};
+use crate::context::{self, RuntimeContext};
+use crate::event::Event;
+use crate::exit::Exited;
+use crate::target::{Target, TargetSlot};
+#[cfg(feature = "tokio")]
+use crate::tokio_bridge::TokioBridge;
+use crate::waker::{create_waker, WakeState};
+use crate::{Events, LagPolicy, Message, UserEvent};
+
+/// A builder for configuring how the future passed to [`run`](crate::run) gets driven.
+///
+/// [`run`](crate::run) and [`run_return`](crate::run_return) use the default configuration;
+/// [`Runtime::run`] and [`Runtime::run_return`] are the same, but with the configuration
+/// specified here.
+///
+/// `T` and `W` are the user event and window target types of the event loop it's going to run,
+/// which only need to be known for [`on_event_sync`](Self::on_event_sync); they're usually
+/// inferred.
+pub struct Runtime<T: 'static = (), W: 'static = EventLoopWindowTarget<UserEvent<T>>> {
+    poll_mode: PollMode,
+    control_flow: ControlFlow,
+    coalesce: Coalesce,
+    lag_policy: LagPolicy,
+    max_blocking_threads: Option<usize>,
+    #[cfg(feature = "tokio")]
+    tokio_handle: Option<tokio::runtime::Handle>,
+    hooks: Vec<Box<SyncHook<T, W>>>,
+}
+
+type SyncHook<T, W> = dyn FnMut(&mut Event<T>, &W) -> Propagation;
+
+/// When the future passed to [`run`](crate::run) gets polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PollMode {
+    /// Poll the future once per batch of events, at [`Event::MainEventsCleared`].
+    ///
+    /// Events still get sent to the [`Events`] stream as soon as they arrive, so none of them are
+    /// lost, but a burst of events (e.g. [`CursorMoved`](crate::event::WindowEvent::CursorMoved)
+    /// while the mouse is moving) only costs a single poll.
+    ///
+    /// [`Event::RedrawRequested`] and [`WindowEvent::ScaleFactorChanged`] still get polled right
+    /// away, since they need to be responded to before the event loop moves on. Other events
+    /// which come after [`Event::MainEventsCleared`], like [`Event::RedrawEventsCleared`], are
+    /// held back until the next poll instead.
+    ///
+    /// [`WindowEvent::ScaleFactorChanged`]: crate::event::WindowEvent::ScaleFactorChanged
+    #[default]
+    Batched,
+    /// Poll the future after every event.
+    Immediate,
+}
+
+/// Whether an event handled by a [`Runtime::on_event_sync`] hook should be passed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// Pass the event on to the next hook, and then the futures.
+    Continue,
+    /// Consume the event, so that nothing else sees it.
+    Stop,
+}
+
+impl<T, W> Default for Runtime<T, W> {
+    fn default() -> Self {
+        Self {
+            poll_mode: PollMode::default(),
+            control_flow: ControlFlow::Wait,
+            coalesce: Coalesce::none(),
+            lag_policy: LagPolicy::Unbounded,
+            max_blocking_threads: None,
+            #[cfg(feature = "tokio")]
+            tokio_handle: None,
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl<T, W> fmt::Debug for Runtime<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Runtime");
+        debug
+            .field("poll_mode", &self.poll_mode)
+            .field("control_flow", &self.control_flow)
+            .field("coalesce", &self.coalesce)
+            .field("lag_policy", &self.lag_policy)
+            .field("max_blocking_threads", &self.max_blocking_threads);
+        #[cfg(feature = "tokio")]
+        debug.field("tokio_handle", &self.tokio_handle);
+        debug.field("hooks", &self.hooks.len()).finish()
+    }
+}
+
+impl<T, W> Runtime<T, W> {
+    /// Creates a runtime with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets when the future gets polled. Defaults to [`PollMode::Batched`].
+    pub fn poll_mode(mut self, poll_mode: PollMode) -> Self {
+        self.poll_mode = poll_mode;
+        self
+    }
+
+    /// Sets the [`ControlFlow`] the event loop starts out with, which can be changed later with
+    /// [`set_control_flow`](crate::set_control_flow). Defaults to [`ControlFlow::Wait`].
+    ///
+    /// Games will usually want [`ControlFlow::Poll`] here; applications which only need to
+    /// render continuously some of the time can use
+    /// [`request_continuous_frames`](crate::request_continuous_frames) instead.
+    pub fn control_flow(mut self, control_flow: ControlFlow) -> Self {
+        self.control_flow = control_flow;
+        self
+    }
+
+    /// Sets which high-frequency events get coalesced before being sent to the futures. Defaults
+    /// to [`Coalesce::none`].
+    ///
+    /// This keeps a consumer which can't keep up with every
+    /// [`CursorMoved`](crate::event::WindowEvent::CursorMoved) from building up a backlog of
+    /// them.
+    pub fn coalesce(mut self, coalesce: Coalesce) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Sets what the [`Events`] stream passed to the callback does when the future falls behind.
+    /// Defaults to [`LagPolicy::Unbounded`].
+    ///
+    /// Use [`Events::dropped`] to find out how many events have been dropped because of this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy's capacity is 0.
+    pub fn lag_policy(mut self, lag_policy: LagPolicy) -> Self {
+        lag_policy.validate();
+        self.lag_policy = lag_policy;
+        self
+    }
+
+    /// Sets the maximum number of threads used to run closures passed to
+    /// [`spawn_blocking`](crate::spawn_blocking). Defaults to the number of CPUs available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is 0.
+    pub fn max_blocking_threads(mut self, max_threads: usize) -> Self {
+        assert!(max_threads > 0, "`max_threads` must be non-zero");
+        self.max_blocking_threads = Some(max_threads);
+        self
+    }
+
+    /// Runs the future inside an existing tokio runtime's context, rather than starting a new
+    /// one.
+    ///
+    /// By default, a multi-threaded tokio runtime gets started in the background, whose context
+    /// the future and its tasks are always polled in: so [`tokio::spawn`], `tokio::net`,
+    /// `tokio::time` and [`Handle::current`](tokio::runtime::Handle::current) all work from
+    /// inside them. Anything they end up waiting on wakes the event loop from tokio's threads.
+    #[cfg(feature = "tokio")]
+    pub fn tokio_handle(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.tokio_handle = Some(handle);
+        self
+    }
+
+    /// Adds a hook which gets called with every event straight from the event loop's callback,
+    /// before it's sent to the futures.
+    ///
+    /// Some events have to be answered before the event loop's callback returns, which the
+    /// futures can't always manage: they might not get polled in time, or be busy waiting on
+    /// something else. A hook can answer these on the spot, e.g. by picking a new size for
+    /// [`WindowEvent::ScaleFactorChanged`](crate::event::WindowEvent::ScaleFactorChanged), and
+    /// it can change the event before the futures see it, or consume it entirely by returning
+    /// [`Propagation::Stop`].
+    ///
+    /// Hooks are called in the order they were added, with the window target, and before the
+    /// event is [coalesced](Self::coalesce). They don't get called once the future has
+    /// completed. Consuming an event only stops it from being delivered: the runtime still
+    /// handles it as usual, so consuming e.g. [`Event::MainEventsCleared`] still ends the batch.
+    pub fn on_event_sync<H>(mut self, hook: H) -> Self
+    where
+        H: FnMut(&mut Event<T>, &W) -> Propagation + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Like [`run`](crate::run), but using this configuration.
+    pub fn run<B, F, Fut>(self, event_loop: B, callback: F)
+    where
+        B: Backend<UserEvent = T, Target = W>,
+        F: 'static + FnOnce(Target<B::Target>, Events<B::UserEvent>) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let slot = Rc::new(TargetSlot::new());
+        let target = Target::new(slot.clone());
+        let driver = Rc::new(RefCell::new(Driver::new(
+            &event_loop,
+            self,
+            slot,
+            move |events| callback(target, events),
+        )));
+
+        let handler_driver = driver.clone();
+        event_loop.run(move |event, window_target, control_flow| {
+            let destroyed = matches!(event, winit::event::Event::LoopDestroyed);
+            let mut driver = handler_driver.borrow_mut();
+            driver.handle(event, window_target, control_flow);
+            if destroyed && !B::RETURNS_BEFORE_EXIT {
+                // The event loop never gives control back, so this is the last chance to pass on
+                // a panic, even though it has to unwind through the event loop to get anywhere.
+                driver.finish();
+            }
+        });
+
+        if B::RETURNS_BEFORE_EXIT {
+            driver.borrow_mut().finish();
+            process::exit(0);
+        }
+    }
+
+    /// Like [`run_return`](crate::run_return), but using this configuration.
+    pub fn run_return<B, F, Fut>(
+        self,
+        event_loop: &mut B,
+        callback: F,
+    ) -> Result<Fut::Output, Exited>
+    where
+        B: RunReturn<UserEvent = T, Target = W>,
+        F: FnOnce(Target<B::Target>, Events<B::UserEvent>) -> Fut,
+        Fut: Future,
+    {
+        let slot = Rc::new(TargetSlot::new());
+        let target = Target::new(slot.clone());
+        let mut driver = Driver::new(event_loop, self, slot, |events| callback(target, events));
+        event_loop.run_return(|event, window_target, control_flow| {
+            driver.handle(event, window_target, control_flow)
+        });
+        driver.into_output()
+    }
+}
+
+/// Drives a future using the events from an event loop.
+pub(crate) struct Driver<T: 'static, W: 'static, F, Fut: Future> {
+    state: State<T, F, Fut>,
+    config: Runtime<T, W>,
+    /// Whether we're partway through a batch of events, and so going to poll at the end of it.
+    in_batch: bool,
+    coalescer: Coalescer<T>,
+    /// Shared with the wakers, so that they don't bother waking the event loop if we're
+    /// about to poll the future anyway.
+    wake_state: Arc<WakeState>,
+    waker: Waker,
+    context: Rc<RuntimeContext>,
+    /// Where the window target is made available to the futures.
+    slot: Rc<TargetSlot<W>>,
+    // This comes last so that the future gets dropped while it's still running.
+    #[cfg(feature = "tokio")]
+    tokio: TokioBridge,
+}
+
+enum State<T: 'static, F, Fut: Future> {
+    Init(F),
+    Running(Pin<Box<Fut>>, Arc<Mutex<Broadcast<T>>>),
+    Done(Option<Fut::Output>),
+    /// The future or one of the tasks panicked, and the panic is waiting to be resumed once the
+    /// event loop has shut down.
+    Panicked(Box<dyn Any + Send>),
+    /// The event loop was exited through an `ExitHandle` with this exit code.
+    Exited(i32),
+}
+
+impl<T: 'static, W: 'static, F, Fut: Future> Driver<T, W, F, Fut> {
+    pub(crate) fn new<B: Backend<UserEvent = T>>(
+        event_loop: &B,
+        config: Runtime<T, W>,
+        slot: Rc<TargetSlot<W>>,
+        callback: F,
+    ) -> Self {
+        let wake_state = Arc::new(WakeState::new());
+        let waker = create_waker(event_loop.create_proxy(), wake_state.clone());
+        slot.set_waker(waker.clone());
+
+        let context = Rc::new(RuntimeContext::default());
+        context.tasks.set_waker(waker.clone());
+        context.exit.set_waker(waker.clone());
+        context.control_flow.requested.set(config.control_flow);
+        if let Some(max_threads) = config.max_blocking_threads {
+            context.blocking.set_max_threads(max_threads);
+        }
+
+        #[cfg(feature = "tokio")]
+        let tokio = TokioBridge::new(config.tokio_handle.clone());
+
+        Self {
+            state: State::Init(callback),
+            coalescer: Coalescer::new(config.coalesce),
+            config,
+            in_batch: false,
+            wake_state,
+            waker,
+            context,
+            slot,
+            #[cfg(feature = "tokio")]
+            tokio,
+        }
+    }
+
+    /// Handles an event from the event loop.
+    ///
+    /// If the future or one of the tasks panics, the panic is caught rather than unwinding
+    /// through the event loop, and the event loop is told to exit. Use
+    /// [`resume_panic`](Self::resume_panic) to pick the panic back up afterwards.
+    pub(crate) fn handle(
+        &mut self,
+        event: winit::event::Event<'_, UserEvent<T>>,
+        target: &W,
+        control_flow: &mut ControlFlow,
+    ) where
+        F: FnOnce(Events<T>) -> Fut,
+    {
+        let slot = self.slot.clone();
+        let _entered = slot.enter(target);
+        if matches!(event, winit::event::Event::LoopDestroyed) {
+            // Anything sent over from now on will never get run, so make sure nothing's left
+            // waiting on it while the future finishes up.
+            slot.close();
+        }
+
+        let _guard = context::enter(self.context.clone());
+        #[cfg(feature = "tokio")]
+        let _tokio = self.tokio.enter();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.handle_inner(event, target, control_flow)
+        }));
+        if let Err(payload) = result {
+            *control_flow = ControlFlow::Exit;
+
+            // Get rid of everything the panic might have left in a broken state. Dropping it
+            // could panic as well, but the first panic is the one worth reporting.
+            let state = mem::replace(&mut self.state, State::Panicked(payload));
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                drop(state);
+                self.context.tasks.clear();
+                self.context.windows.borrow_mut().close_all();
+            }));
+        }
+    }
+
+    fn handle_inner(
+        &mut self,
+        event: winit::event::Event<'_, UserEvent<T>>,
+        target: &W,
+        control_flow: &mut ControlFlow,
+    ) where
+        F: FnOnce(Events<T>) -> Fut,
+    {
+        let context = &*self.context;
+
+        *control_flow = next_control_flow(context);
+
+        // Once a batch is over, only poll for wake-ups and events that might need answering
+        // before the event loop moves on. Anything else is held back until the next poll, which
+        // the end of the next batch brings about anyway.
+        let hold = self.config.poll_mode == PollMode::Batched
+            && !self.in_batch
+            && !polls_after_batch(&event);
+
+        // Otherwise, we're going to poll the future after dispatching this event (or at the end
+        // of the batch) anyway, so there's no need for anything it wakes up to wake the event
+        // loop as well.
+        if !hold {
+            self.wake_state.start_poll();
+        }
+
+        if matches!(self.state, State::Init(_)) {
+            let callback = match mem::replace(&mut self.state, State::Done(None)) {
+                State::Init(callback) => callback,
+                _ => unreachable!(),
+            };
+            let broadcast = Arc::new(Mutex::new(Broadcast::new()));
+            let subscription = broadcast.lock().unwrap().subscribe(self.config.lag_policy);
+            let events = Events::new(broadcast.clone(), subscription);
+            self.state = State::Running(Box::pin(callback(events)), broadcast);
+        }
+
+        let (mut future, broadcast) = match &mut self.state {
+            State::Init(_) => unreachable!(),
+            State::Running(future, broadcast) => (future.as_mut(), broadcast),
+            State::Done(_) | State::Panicked(_) | State::Exited(_) => return,
+        };
+
+        let event = match event.map_nonuser_event() {
+            Ok(event) => Some(event),
+            Err(winit::event::Event::UserEvent(UserEvent(Message::User(event)))) => {
+                Some(winit::event::Event::UserEvent(event))
+            }
+            Err(winit::event::Event::UserEvent(UserEvent(Message::Wake))) => None,
+            Err(_) => unreachable!("only user events fail to map"),
+        };
+
+        let mut write_back = None;
+        let mut destroyed = false;
+        let mut cleared = false;
+        if let Some(event) = event {
+            destroyed = matches!(event, winit::event::Event::LoopDestroyed);
+            match event {
+                winit::event::Event::NewEvents(_) => self.in_batch = true,
+                winit::event::Event::MainEventsCleared => {
+                    self.in_batch = false;
+                    cleared = true;
+                }
+                _ => {}
+            }
+
+            let (mut event, size_write_back) = Event::from_winit(event);
+            write_back = size_write_back;
+
+            let propagation = self
+                .config
+                .hooks
+                .iter_mut()
+                .map(|hook| hook(&mut event, target))
+                .find(|&propagation| propagation == Propagation::Stop)
+                .unwrap_or(Propagation::Continue);
+
+            if propagation == Propagation::Continue {
+                if hold {
+                    self.coalescer.hold(event);
+                } else {
+                    self.coalescer
+                        .push(event, |event| send_event(context, broadcast, event));
+                }
+            }
+        }
+
+        if hold {
+            return;
+        }
+
+        if destroyed {
+            // This is the last event we'll ever get, so end the stream and let the future
+            // finish whatever cleanup it has left.
+            broadcast.lock().unwrap().close();
+            context.windows.borrow_mut().close_all();
+            let output = block_on(future, context);
+            self.state = State::Done(Some(output));
+            return;
+        }
+
+        // Leave polling until the end of the batch, unless the future needs to respond to this
+        // event before the event loop moves on.
+        if self.config.poll_mode == PollMode::Batched && self.in_batch && write_back.is_none() {
+            return;
+        }
+
+        if let Some(code) = context.exit.requested() {
+            self.exit(code, control_flow);
+            return;
+        }
+
+        // The future's about to see everything that's arrived so far, including any events being
+        // held back to merge with later ones.
+        self.coalescer
+            .flush(|event| send_event(context, broadcast, event));
+
+        wake_expired_timers(context);
+
+        // Everything woken so far is covered by this poll, but anything woken from here on
+        // might not be.
+        self.wake_state.start_poll();
+
+        // Code sent over by `MainThreadHandle`s doesn't get polled, so run it here. This has to
+        // come after `start_poll`: anything sent over from now on wakes the event loop again,
+        // whereas anything sent before would have been forgotten about by it.
+        self.slot.run_jobs(target);
+
+        let mut went_idle = false;
+        loop {
+            match future.as_mut().poll(&mut Context::from_waker(&self.waker)) {
+                Poll::Ready(output) => {
+                    *control_flow = ControlFlow::Exit;
+                    self.state = State::Done(Some(output));
+                    context.tasks.clear();
+                }
+                Poll::Pending => {
+                    context.tasks.run_ready();
+
+                    let woken = self.wake_state.finish_poll();
+
+                    // If nothing's left to do at the end of the batch, the event loop is idle:
+                    // let anything waiting for that run before it goes to sleep. Only do this
+                    // once per batch, so that waiting for idle again in a loop doesn't spin.
+                    if cleared && !woken && !went_idle && context.idle.is_awaited() {
+                        went_idle = true;
+                        // Whatever this wakes is about to get polled, so it mustn't make the event
+                        // loop run another batch: wake it as part of a poll, and then start a
+                        // fresh one which covers it.
+                        self.wake_state.start_poll();
+                        context.idle.wake_all();
+                        self.wake_state.start_poll();
+                        continue;
+                    }
+
+                    // Anything which started waiting for idle between batches would otherwise
+                    // be stuck until some unrelated event comes along, so start a batch for it.
+                    // Anything which started waiting at the end of a batch waits for the next.
+                    let idle_registered = context.idle.take_registered();
+                    let needs_batch = idle_registered && !cleared && !self.in_batch;
+
+                    // Either the future or a task was woken up while we were polling, so go
+                    // around again.
+                    if woken || needs_batch {
+                        self.waker.wake_by_ref();
+                    }
+
+                    *control_flow = next_control_flow(context);
+
+                    // Don't give the future a chance to run again if it's just asked to exit.
+                    if let Some(code) = context.exit.requested() {
+                        self.exit(code, control_flow);
+                    }
+                }
+            }
+            break;
+        }
+
+        // The future has had its chance to pick a new size, so pass it on to winit.
+        if let Some(write_back) = write_back {
+            write_back.write_back();
+        }
+    }
+
+    /// Exits the event loop, cancelling the future and all the tasks.
+    fn exit(&mut self, code: i32, control_flow: &mut ControlFlow) {
+        *control_flow = ControlFlow::Exit;
+
+        // Cancel the tasks before the future, since they're usually spawned by it and may still
+        // be relying on things it owns.
+        self.context.tasks.clear();
+        self.state = State::Exited(code);
+        self.context.windows.borrow_mut().close_all();
+    }
+
+    /// Resumes the panic caught by [`handle`](Self::handle) if there was one, or exits the
+    /// process if the event loop was exited through an `ExitHandle` with a non-zero code.
+    fn finish(&mut self) {
+        self.resume_panic();
+        match self.state {
+            State::Exited(code) if code != 0 => process::exit(code),
+            _ => {}
+        }
+    }
+
+    /// Resumes the panic caught by [`handle`](Self::handle), if there was one.
+    pub(crate) fn resume_panic(&mut self) {
+        if let State::Panicked(_) = self.state {
+            match mem::replace(&mut self.state, State::Done(None)) {
+                State::Panicked(payload) => panic::resume_unwind(payload),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Returns the future's output, or the exit code if the event loop was exited before it
+    /// completed.
+    ///
+    /// This resumes the panic caught by [`handle`](Self::handle), if there was one.
+    pub(crate) fn into_output(mut self) -> Result<Fut::Output, Exited> {
+        self.resume_panic();
+        match self.state {
+            State::Done(Some(output)) => Ok(output),
+            State::Exited(code) => Err(Exited::new(code)),
+            _ => panic!("event loop exited before the future completed"),
+        }
+    }
+}
+
+/// Sends an event to the future's [`Events`] streams, and the window streams it's for.
+fn send_event<T>(context: &RuntimeContext, broadcast: &Mutex<Broadcast<T>>, event: Event<T>) {
+    match &event {
+        Event::WindowEvent { window_id, event } => {
+            context.windows.borrow_mut().dispatch(*window_id, event);
+        }
+        Event::RedrawRequested(window_id) => {
+            context
+                .windows
+                .borrow_mut()
+                .redraw(*window_id, Instant::now());
+        }
+        _ => {}
+    }
+
+    broadcast.lock().unwrap().send(event);
+}
+
+/// Returns whether the future needs to be polled for `event` if it arrives outside of a batch of
+/// events.
+fn polls_after_batch<T>(event: &winit::event::Event<'_, UserEvent<T>>) -> bool {
+    use winit::event::Event;
+
+    matches!(
+        event,
+        // These start and end batches, rather than coming after one.
+        Event::NewEvents(_)
+            | Event::MainEventsCleared
+            | Event::LoopDestroyed
+            // Something's been woken up, and is waiting to be polled.
+            | Event::UserEvent(UserEvent(Message::Wake))
+            // These need to be answered before the event loop moves on.
+            | Event::RedrawRequested(_)
+            | Event::WindowEvent {
+                event: winit::event::WindowEvent::ScaleFactorChanged { .. },
+                ..
+            }
+    )
+}
+
+/// Works out the control flow the event loop should follow, making sure it wakes up in time for
+/// the next timer.
+fn next_control_flow(context: &RuntimeContext) -> ControlFlow {
+    let next_deadline = context.timers.borrow().next_deadline();
+    context.control_flow.resolve(Instant::now(), next_deadline)
+}
+
+fn wake_expired_timers(context: &RuntimeContext) {
+    let expired = context.timers.borrow_mut().take_expired(Instant::now());
+    for waker in expired {
+        waker.wake();
+    }
+}
+
+/// Drives a future to completion by blocking the current thread.
+///
+/// This is used once the event loop has been destroyed, since there's no longer any loop around
+/// to wake. Timers, spawned tasks and [`idle`](crate::idle) still work, and the tasks are
+/// cancelled once the future completes.
+fn block_on<Fut: Future>(mut future: Pin<&mut Fut>, context: &RuntimeContext) -> Fut::Output {
+    struct ThreadWaker {
+        thread: Thread,
+        woken: AtomicBool,
+    }
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref()
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.woken.store(true, Ordering::Release);
+            self.thread.unpark()
+        }
+    }
+
+    let thread_waker = Arc::new(ThreadWaker {
+        thread: thread::current(),
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(thread_waker.clone());
+    context.tasks.set_waker(waker.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let output = loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            break output;
+        }
+
+        context.tasks.run_ready();
+
+        // There are no more batches for anything waiting for idle to wait for, so it's idle
+        // whenever nothing's been woken.
+        if !thread_waker.woken.swap(false, Ordering::Acquire) && context.idle.is_awaited() {
+            context.idle.wake_all();
+            continue;
+        }
+
+        let next_deadline = context.timers.borrow().next_deadline();
+        match next_deadline {
+            Some(deadline) => {
+                thread::park_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => thread::park(),
+        }
+        wake_expired_timers(context);
+    };
+
+    context.tasks.clear();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::future::Future;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use winit::dpi::PhysicalSize;
+    use winit::event_loop::ControlFlow;
+
+    use super::{PollMode, Propagation, Runtime};
+    use crate::backend::fake::{self, FakeEventLoop};
+    use crate::event::{Event, NewInnerSize, WindowEvent};
+
+    #[test]
+    fn run_return_returns_the_futures_output() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        // The future doesn't need to be `'static`, so it can borrow this.
+        let prefix = String::from("got");
+        let prefix = &prefix;
+
+        // The event loop can be run again once it's returned.
+        for i in [3, 4] {
+            event_loop.push_event(Event::UserEvent(i));
+            let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+                while let Some(event) = events.next().await {
+                    if let Event::UserEvent(i) = event {
+                        return format!("{prefix} {i}");
+                    }
+                }
+                unreachable!("the event loop was destroyed before it sent the user event")
+            });
+            assert_eq!(output.unwrap(), format!("got {i}"));
+        }
+    }
+
+    #[test]
+    fn run_return_exits_once_the_future_completes() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        let proxy = event_loop.create_proxy();
+        event_loop.push_event(Event::UserEvent(1));
+        event_loop.push_event(Event::UserEvent(2));
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            events.next().await.unwrap()
+        });
+        assert!(matches!(output.unwrap(), Event::NewEvents(_)));
+        assert_eq!(proxy.control_flows().last(), Some(&ControlFlow::Exit));
+    }
+
+    #[test]
+    fn run_return_resumes_panics_once_the_loop_exits() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        event_loop.push_event(Event::UserEvent(3));
+        let proxy = event_loop.create_proxy();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::run_return(&mut event_loop, |_, mut events| async move {
+                events.next().await;
+                panic!("boom");
+            })
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(*payload.downcast_ref::<&str>().unwrap(), "boom");
+        assert_eq!(proxy.control_flows().last(), Some(&ControlFlow::Exit));
+    }
+
+    #[test]
+    fn run_resumes_panics_from_tasks() {
+        let event_loop = FakeEventLoop::<u32>::new();
+        event_loop.push_event(Event::UserEvent(3));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::run(event_loop, |_, mut events| async move {
+                crate::spawn_local(async { panic!("task") }).detach();
+                while events.next().await.is_some() {}
+            })
+        }));
+        assert_eq!(*result.unwrap_err().downcast_ref::<&str>().unwrap(), "task");
+    }
+
+    #[test]
+    fn panics_cancel_everything_else() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        let guard = Rc::new(());
+
+        let task_guard = guard.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::run_return(&mut event_loop, |_, _| async move {
+                crate::spawn_local(async move {
+                    let _guard = task_guard;
+                    std::future::pending::<()>().await
+                })
+                .detach();
+                crate::sleep(Duration::ZERO).await;
+                panic!("boom");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&guard), 1);
+    }
+
+    /// Counts how many times the future it wraps gets polled.
+    struct CountPolls<F> {
+        future: Pin<Box<F>>,
+        polls: Rc<Cell<u32>>,
+    }
+
+    impl<F: Future> Future for CountPolls<F> {
+        type Output = F::Output;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+            self.polls.set(self.polls.get() + 1);
+            self.future.as_mut().poll(cx)
+        }
+    }
+
+    /// Runs the event loop for 10 iterations, each with a handful of user events, returning how
+    /// many times the future was polled and how many `RedrawEventsCleared` events it saw.
+    fn count_polls(poll_mode: PollMode) -> (u32, u32) {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        let proxy = event_loop.create_proxy();
+        let polls = Rc::new(Cell::new(0));
+
+        let output =
+            Runtime::new()
+                .poll_mode(poll_mode)
+                .run_return(&mut event_loop, |_, mut events| CountPolls {
+                    future: Box::pin(async move {
+                        let mut iterations = 0;
+                        let mut redraws_cleared = 0;
+                        while let Some(event) = events.next().await {
+                            match event {
+                                Event::MainEventsCleared => {
+                                    iterations += 1;
+                                    if iterations == 10 {
+                                        break;
+                                    }
+                                    // Keep the event loop going.
+                                    for i in 0..5 {
+                                        proxy.push_event(Event::UserEvent(i));
+                                    }
+                                }
+                                Event::RedrawEventsCleared => redraws_cleared += 1,
+                                _ => {}
+                            }
+                        }
+                        redraws_cleared
+                    }),
+                    polls: polls.clone(),
+                });
+        (polls.get(), output.unwrap())
+    }
+
+    #[test]
+    fn batched_polling_polls_once_per_batch() {
+        // The `RedrawEventsCleared` at the end of each iteration gets delivered with the next
+        // poll, rather than being polled for on its own.
+        assert_eq!(count_polls(PollMode::Batched), (10, 9));
+
+        let (polls, redraws_cleared) = count_polls(PollMode::Immediate);
+        assert!(polls > 50);
+        assert_eq!(redraws_cleared, 9);
+    }
+
+    #[test]
+    fn batched_polling_still_answers_scale_factor_changes() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let window_id = fake::window_id();
+        let new_inner_size = NewInnerSize::new(PhysicalSize::new(10, 10));
+        event_loop.push_event(Event::WindowEvent {
+            window_id,
+            event: WindowEvent::ScaleFactorChanged {
+                scale_factor: 2.0,
+                new_inner_size: new_inner_size.clone(),
+            },
+        });
+
+        let output = crate::run_return(&mut event_loop, |_, mut events| async move {
+            while let Some(event) = events.next().await {
+                if let Event::WindowEvent {
+                    event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
+                    ..
+                } = event
+                {
+                    new_inner_size.set(PhysicalSize::new(20, 20));
+                    return;
+                }
+            }
+        });
+        output.unwrap();
+        assert_eq!(new_inner_size.get(), PhysicalSize::new(20, 20));
+    }
+
+    #[test]
+    fn hooks_can_change_and_consume_events() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        for i in 1..=3 {
+            event_loop.push_event(Event::UserEvent(i));
+        }
+        let hooked = Rc::new(RefCell::new(Vec::new()));
+
+        let output = Runtime::new()
+            .on_event_sync({
+                let hooked = hooked.clone();
+                move |event, _| {
+                    if let Event::UserEvent(i) = event {
+                        hooked.borrow_mut().push(*i);
+                        if *i == 1 {
+                            return Propagation::Stop;
+                        }
+                        *i *= 10;
+                    }
+                    Propagation::Continue
+                }
+            })
+            .run_return(&mut event_loop, |_, mut events| async move {
+                let mut received = Vec::new();
+                while let Some(event) = events.next().await {
+                    if let Event::UserEvent(i) = event {
+                        received.push(i);
+                        if i == 30 {
+                            break;
+                        }
+                    }
+                }
+                received
+            });
+
+        assert_eq!(output.unwrap(), [20, 30]);
+        assert_eq!(*hooked.borrow(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn hooks_run_in_order_until_one_consumes_the_event() {
+        let mut event_loop = FakeEventLoop::<u32>::new();
+        event_loop.push_event(Event::UserEvent(1));
+        event_loop.push_event(Event::UserEvent(2));
+        let calls = Rc::new(RefCell::new(Vec::new()));
+
+        let hook = |name: &'static str, stop_at: u32| {
+            let calls = calls.clone();
+            move |event: &mut Event<u32>, _: &_| match event {
+                Event::UserEvent(i) => {
+                    calls.borrow_mut().push((name, *i));
+                    if *i == stop_at {
+                        Propagation::Stop
+                    } else {
+                        Propagation::Continue
+                    }
+                }
+                _ => Propagation::Continue,
+            }
+        };
+
+        let output = Runtime::new()
+            .on_event_sync(hook("first", 1))
+            .on_event_sync(hook("second", 2))
+            .run_return(&mut event_loop, |_, mut events| async move {
+                let mut received = Vec::new();
+                while let Some(event) = events.next().await {
+                    if let Event::UserEvent(i) = event {
+                        received.push(i);
+                    }
+                }
+                received
+            });
+
+        assert_eq!(output.unwrap(), []);
+        assert_eq!(*calls.borrow(), [("first", 1), ("first", 2), ("second", 2)]);
+    }
+}
@@ -0,0 +1,189 @@
+//! Scoped access to the event loop's window target.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use winit::error::OsError;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::{Window, WindowBuilder};
+
+use crate::main_thread::{JobQueue, MainThreadHandle};
+
+/// A handle to the event loop's window target (usually an [`EventLoopWindowTarget`]), passed to
+/// [`run`](crate::run)'s callback.
+///
+/// winit only hands out the window target while it's dispatching an event, so this only gives
+/// access to it for the duration of [`with`](Self::with). The future passed to
+/// [`run`](crate::run) and any spawned tasks are only ever polled while an event is being
+/// dispatched, so from within them the target is always available.
+pub struct Target<W: 'static> {
+    slot: Rc<TargetSlot<W>>,
+}
+
+impl<W> Target<W> {
+    pub(crate) fn new(slot: Rc<TargetSlot<W>>) -> Self {
+        Self { slot }
+    }
+
+    /// Calls `f` with the window target.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the event loop isn't currently dispatching an event.
+    pub fn with<R>(&self, f: impl FnOnce(&W) -> R) -> R {
+        self.try_with(f).expect(
+            "the window target can only be used while the event loop is dispatching an event",
+        )
+    }
+
+    /// Calls `f` with the window target, or returns `None` if the event loop isn't currently
+    /// dispatching an event.
+    pub fn try_with<R>(&self, f: impl FnOnce(&W) -> R) -> Option<R> {
+        let target = self.slot.current.get()?;
+        // SAFETY: `current` is only set while `TargetSlot::enter`'s guard is alive, which
+        // borrows the target for as long as that is.
+        Some(f(unsafe { target.as_ref() }))
+    }
+
+    /// Returns a handle for running code with the window target from other threads.
+    pub fn main_thread_handle(&self) -> MainThreadHandle<W> {
+        MainThreadHandle::new(self.slot.jobs.clone())
+    }
+}
+
+impl<T: 'static> Target<EventLoopWindowTarget<T>> {
+    /// Creates a new window from `builder`.
+    ///
+    /// This completes straight away when awaited from within [`run`](crate::run); otherwise it
+    /// waits until the event loop dispatches its next event.
+    pub fn create_window(&self, builder: WindowBuilder) -> CreateWindow<'_, T> {
+        CreateWindow {
+            target: self,
+            builder: Some(builder),
+        }
+    }
+}
+
+impl<W> Clone for Target<W> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<W> fmt::Debug for Target<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Target")
+            .field("dispatching", &self.slot.current.get().is_some())
+            .finish()
+    }
+}
+
+/// A future returned by [`Target::create_window`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CreateWindow<'a, T: 'static> {
+    target: &'a Target<EventLoopWindowTarget<T>>,
+    builder: Option<WindowBuilder>,
+}
+
+impl<T> Future for CreateWindow<'_, T> {
+    type Output = Result<Window, OsError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let builder = self
+            .builder
+            .take()
+            .expect("`CreateWindow` polled after completion");
+
+        // We need to hand the builder back if the target isn't available, so use a cell to get
+        // it in and out of the closure.
+        let builder = Cell::new(Some(builder));
+        let result = self
+            .target
+            .try_with(|target| builder.take().unwrap().build(target));
+
+        match result {
+            Some(result) => Poll::Ready(result),
+            None => {
+                self.builder = builder.take();
+                self.target
+                    .slot
+                    .waiters
+                    .borrow_mut()
+                    .push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Where the window target lives while the event loop is dispatching an event.
+pub(crate) struct TargetSlot<W> {
+    current: Cell<Option<NonNull<W>>>,
+    /// Woken once the target becomes available.
+    waiters: RefCell<Vec<Waker>>,
+    /// Code sent over by `MainThreadHandle`s, waiting for the target to become available.
+    jobs: Arc<JobQueue<W>>,
+}
+
+impl<W> TargetSlot<W> {
+    pub(crate) fn new() -> Self {
+        Self {
+            current: Cell::new(None),
+            waiters: RefCell::new(Vec::new()),
+            jobs: Arc::new(JobQueue::new()),
+        }
+    }
+
+    /// Sets the waker that gets woken whenever a `MainThreadHandle` sends over some code to run.
+    pub(crate) fn set_waker(&self, waker: Waker) {
+        self.jobs.set_waker(waker);
+    }
+
+    /// Makes `target` available through [`Target::with`] until the returned guard is dropped.
+    pub(crate) fn enter<'a>(&'a self, target: &'a W) -> EnterGuard<'a, W> {
+        let prev = self.current.replace(Some(NonNull::from(target)));
+
+        let waiters = self.waiters.take();
+        for waker in waiters {
+            waker.wake();
+        }
+
+        EnterGuard {
+            slot: self,
+            prev,
+            _target: PhantomData,
+        }
+    }
+
+    /// Runs any code `MainThreadHandle`s have sent over.
+    pub(crate) fn run_jobs(&self, target: &W) {
+        self.jobs.run(target);
+    }
+
+    /// Stops accepting code from `MainThreadHandle`s, once the event loop is gone.
+    pub(crate) fn close(&self) {
+        self.jobs.close();
+    }
+}
+
+pub(crate) struct EnterGuard<'a, W> {
+    slot: &'a TargetSlot<W>,
+    prev: Option<NonNull<W>>,
+    _target: PhantomData<&'a W>,
+}
+
+impl<W> Drop for EnterGuard<'_, W> {
+    fn drop(&mut self) {
+        self.slot.current.set(self.prev);
+    }
+}
@@ -0,0 +1,328 @@
+//! Spawning tasks onto the event loop thread, and offloading blocking work from it.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::context;
+
+mod blocking;
+
+pub(crate) use blocking::BlockingPool;
+pub use blocking::{spawn_blocking, BlockingJoinHandle};
+
+/// Spawns a task onto the event loop thread.
+///
+/// The task is polled by [`run`](crate::run) alongside the main future, so it doesn't need to be
+/// `Send`. It's only polled again once it's been woken up.
+///
+/// The task is cancelled if the returned [`JoinHandle`] is dropped; use [`JoinHandle::detach`]
+/// to let it keep running in the background instead. All remaining tasks are cancelled once the
+/// main future completes.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let state = Rc::new(JoinState {
+        output: Cell::new(None),
+        waker: Cell::new(None),
+    });
+
+    let task = {
+        let state = state.clone();
+        async move {
+            state.output.set(Some(future.await));
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    };
+
+    let id = context::with(|context| context.tasks.spawn(Box::pin(task)));
+
+    JoinHandle {
+        id,
+        state,
+        detached: false,
+    }
+}
+
+/// A handle to a task spawned with [`spawn_local`], which can be awaited to get the task's
+/// output.
+///
+/// Dropping the handle cancels the task.
+#[must_use = "dropping a `JoinHandle` cancels the task"]
+pub struct JoinHandle<T> {
+    id: u64,
+    state: Rc<JoinState<T>>,
+    detached: bool,
+}
+
+struct JoinState<T> {
+    output: Cell<Option<T>>,
+    waker: Cell<Option<Waker>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task.
+    ///
+    /// This is the same as dropping the handle, but more explicit.
+    pub fn abort(self) {}
+
+    /// Lets the task keep running in the background, without anything waiting on its output.
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match self.state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                self.state.waker.set(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if !self.detached {
+            context::try_with(|context| context.tasks.abort(self.id));
+        }
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The tasks spawned onto a runtime.
+#[derive(Default)]
+pub(crate) struct Executor {
+    tasks: RefCell<HashMap<u64, Task>>,
+    ready: Arc<ReadyQueue>,
+    /// The ID of the task currently being polled, if any.
+    polling: Cell<Option<u64>>,
+    /// Set if the task currently being polled gets aborted while it's running.
+    aborted: Cell<bool>,
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// The IDs of the tasks which have been woken, shared with their wakers.
+#[derive(Default)]
+struct ReadyQueue {
+    ids: Mutex<VecDeque<u64>>,
+    /// Woken whenever a task is, to get the event loop to run it.
+    waker: Mutex<Option<Waker>>,
+}
+
+struct TaskWaker {
+    id: u64,
+    /// Whether this task is already in the ready queue.
+    scheduled: AtomicBool,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.ready.ids.lock().unwrap().push_back(self.id);
+        let waker = self.ready.waker.lock().unwrap().clone();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Executor {
+    /// Sets the waker that gets woken whenever there are tasks ready to run.
+    pub(crate) fn set_waker(&self, waker: Waker) {
+        *self.ready.waker.lock().unwrap() = Some(waker);
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()>>>) -> u64 {
+        // These are unique across runtimes, so that a `JoinHandle` can't abort the wrong task if
+        // it's dropped in a different one.
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        let waker = Arc::new(TaskWaker {
+            id,
+            scheduled: AtomicBool::new(false),
+            ready: self.ready.clone(),
+        });
+        self.tasks.borrow_mut().insert(
+            id,
+            Task {
+                future,
+                waker: waker.clone(),
+            },
+        );
+        // Get the task polled for the first time.
+        waker.wake();
+
+        id
+    }
+
+    fn abort(&self, id: u64) {
+        if self.polling.get() == Some(id) {
+            self.aborted.set(true);
+        }
+
+        // Make sure the task is dropped after the borrow ends, in case dropping it aborts
+        // other tasks.
+        let task = self.tasks.borrow_mut().remove(&id);
+        drop(task);
+    }
+
+    /// Polls every task which has been woken since the last call.
+    ///
+    /// Tasks woken while this is running won't be polled until the next call, so that a task
+    /// which keeps waking itself can't hold up the event loop.
+    pub(crate) fn run_ready(&self) {
+        let ids = mem::take(&mut *self.ready.ids.lock().unwrap());
+        for id in ids {
+            let mut task = match self.tasks.borrow_mut().remove(&id) {
+                Some(task) => task,
+                // It's already finished or been aborted.
+                None => continue,
+            };
+
+            task.waker.scheduled.store(false, Ordering::Release);
+            let waker = Waker::from(task.waker.clone());
+
+            self.polling.set(Some(id));
+            let poll = task.future.as_mut().poll(&mut Context::from_waker(&waker));
+            self.polling.set(None);
+
+            let aborted = self.aborted.replace(false);
+            if poll.is_pending() && !aborted {
+                self.tasks.borrow_mut().insert(id, task);
+            }
+        }
+    }
+
+    /// Cancels all the remaining tasks, in the order they were spawned.
+    pub(crate) fn clear(&self) {
+        let mut tasks: Vec<_> = mem::take(&mut *self.tasks.borrow_mut())
+            .into_iter()
+            .collect();
+        // IDs are handed out in increasing order.
+        tasks.sort_by_key(|&(id, _)| id);
+        for (_, task) in tasks {
+            drop(task);
+        }
+    }
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::spawn_local;
+    use crate::backend::fake::FakeEventLoop;
+
+    #[test]
+    fn join_handles_resolve_to_the_tasks_output() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let output = Rc::new(Cell::new(None));
+
+        let result = output.clone();
+        crate::run(event_loop, move |_, _| async move {
+            let slow = spawn_local(async {
+                crate::sleep(Duration::from_millis(10)).await;
+                5
+            });
+            let nested = spawn_local(async { spawn_local(async { 7 }).await });
+            result.set(Some(slow.await + nested.await));
+        });
+        assert_eq!(output.get(), Some(12));
+    }
+
+    #[test]
+    fn aborted_tasks_stop_running() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let ran = Rc::new(Cell::new(false));
+        let guard = Rc::new(());
+        let output = Rc::new(Cell::new(None));
+
+        let (task_ran, task_guard, result) = (ran.clone(), guard.clone(), output.clone());
+        crate::run(event_loop, move |_, _| async move {
+            let task = spawn_local(async move {
+                let _guard = task_guard;
+                crate::sleep(Duration::from_millis(10)).await;
+                task_ran.set(true);
+            });
+            crate::sleep(Duration::from_millis(1)).await;
+            task.abort();
+            let dropped = Rc::strong_count(&guard) == 1;
+            crate::sleep(Duration::from_millis(20)).await;
+            result.set(Some(dropped));
+        });
+        assert_eq!(output.get(), Some(true));
+        assert!(!ran.get());
+    }
+
+    #[test]
+    fn detached_tasks_run_until_the_future_completes() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let count = Rc::new(Cell::new(0));
+        let guard = Rc::new(());
+
+        let (task_count, task_guard) = (count.clone(), guard.clone());
+        crate::run(event_loop, move |_, _| async move {
+            spawn_local(async move {
+                let _guard = task_guard;
+                loop {
+                    task_count.set(task_count.get() + 1);
+                    crate::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .detach();
+            crate::sleep(Duration::from_millis(20)).await;
+        });
+        assert!(count.get() > 1);
+        // The task was cancelled along with the rest of the runtime.
+        assert_eq!(Rc::strong_count(&guard), 1);
+    }
+}
@@ -0,0 +1,246 @@
+//! Running blocking code on a thread pool, without holding up the event loop.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+
+use crate::{context, oneshot};
+
+/// Runs `f` on a background thread, returning a handle which can be awaited to get its result.
+///
+/// Use this for CPU-heavy or otherwise blocking work (e.g. decoding an image) which would
+/// otherwise stop the event loop from handling events until it's done. The result comes back to
+/// the event loop thread, which gets woken up once it's ready.
+///
+/// The threads come from a pool which belongs to the runtime, and which grows as needed up to
+/// [`Runtime::max_blocking_threads`](crate::Runtime::max_blocking_threads). Once all of them are
+/// busy, closures wait in line for one to free up.
+///
+/// # Panics
+///
+/// Panics if called outside of [`run`](crate::run).
+pub fn spawn_blocking<F, R>(f: F) -> BlockingJoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    // Any panic gets sent back to whoever's waiting on the result, rather than losing the thread.
+    let job = Box::new(move || tx.run(f));
+    context::with(|context| context.blocking.spawn(job));
+    BlockingJoinHandle { rx }
+}
+
+/// A handle to a closure run with [`spawn_blocking`], which can be awaited to get its result.
+///
+/// Dropping the handle doesn't stop the closure from running, since there's no way to interrupt
+/// it; its result just gets thrown away.
+///
+/// # Panics
+///
+/// Awaiting the handle panics if the closure panicked, resuming the panic, or if the runtime
+/// shut down before the closure got a chance to run.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct BlockingJoinHandle<R> {
+    rx: oneshot::Receiver<R>,
+}
+
+impl<R> Future for BlockingJoinHandle<R> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|output| output.expect("the runtime shut down before the blocking task could run"))
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The thread pool used by [`spawn_blocking`].
+///
+/// Threads are only started once there's something for them to do, and stick around until the
+/// pool is dropped.
+pub(crate) struct BlockingPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when a job is queued or the pool shuts down.
+    condvar: Condvar,
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    /// The number of threads waiting for a job.
+    idle: usize,
+    max_threads: usize,
+    shutdown: bool,
+}
+
+impl BlockingPool {
+    pub(crate) fn set_max_threads(&self, max_threads: usize) {
+        self.shared.state.lock().unwrap().max_threads = max_threads;
+    }
+
+    fn spawn(&self, job: Job) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.push_back(job);
+
+        // Every queued job has an idle thread on its way to pick it up, as long as there are at
+        // least as many of them as there are jobs.
+        if state.queue.len() <= state.idle {
+            self.shared.condvar.notify_one();
+        } else if state.threads < state.max_threads {
+            state.threads += 1;
+            let shared = self.shared.clone();
+            thread::Builder::new()
+                .name("winit-async-blocking".into())
+                .spawn(move || shared.run_worker())
+                .expect("failed to spawn a blocking thread");
+        }
+    }
+}
+
+impl Shared {
+    fn run_worker(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            if state.shutdown {
+                break;
+            }
+
+            state.idle += 1;
+            state = self
+                .condvar
+                .wait_while(state, |state| state.queue.is_empty() && !state.shutdown)
+                .unwrap();
+            state.idle -= 1;
+        }
+        state.threads -= 1;
+    }
+}
+
+impl Default for BlockingPool {
+    fn default() -> Self {
+        let max_threads = thread::available_parallelism().map_or(4, |n| n.get());
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                    max_threads,
+                    shutdown: false,
+                }),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        // Let the threads finish what they're doing, but don't wait for them. Anything that
+        // hasn't started yet gets dropped, which tells whoever's waiting on it.
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown = true;
+        let queue = std::mem::take(&mut state.queue);
+        drop(state);
+        drop(queue);
+        self.shared.condvar.notify_all();
+    }
+}
+
+impl fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingPool").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::backend::fake::FakeEventLoop;
+    use crate::Runtime;
+
+    #[test]
+    fn runs_closures_on_other_threads() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+        let main = thread::current().id();
+
+        let output =
+            Runtime::new()
+                .max_blocking_threads(2)
+                .run_return(&mut event_loop, |_, _| async move {
+                    let handles: Vec<_> = (0..5)
+                        .map(|i| crate::spawn_blocking(move || (i * 2, thread::current().id())))
+                        .collect();
+                    let mut sum = 0;
+                    for handle in handles {
+                        let (value, id) = handle.await;
+                        assert_ne!(id, main);
+                        sum += value;
+                    }
+                    sum
+                });
+        assert_eq!(output.unwrap(), 20);
+    }
+
+    #[test]
+    fn resumes_panics() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::run_return(&mut event_loop, |_, _| async {
+                crate::spawn_blocking(|| -> u32 { panic!("blocking") }).await
+            })
+        }));
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "blocking");
+    }
+
+    #[test]
+    fn uses_every_thread_it_can() {
+        // Each closure waits for all the others to start, so this only finishes if they all get
+        // a thread of their own, both when the threads are started and once they're idle.
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let output =
+            Runtime::new()
+                .max_blocking_threads(3)
+                .run_return(&mut event_loop, |_, _| async {
+                    for _ in 0..2 {
+                        let barrier = Arc::new(Barrier::new(3));
+                        let handles: Vec<_> = (0..3)
+                            .map(|_| {
+                                let barrier = barrier.clone();
+                                crate::spawn_blocking(move || {
+                                    barrier.wait();
+                                })
+                            })
+                            .collect();
+                        for handle in handles {
+                            handle.await;
+                        }
+                    }
+                });
+        output.unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! Timers and intervals driven by the event loop.
+//!
+//! Rather than needing a separate timer thread, pending deadlines are collected by
+//! [`run`](crate::run), which waits for the earliest one using [`ControlFlow::WaitUntil`].
+//!
+//! [`ControlFlow::WaitUntil`]: winit::event_loop::ControlFlow::WaitUntil
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+
+use crate::context;
+
+/// Waits until `duration` has elapsed.
+///
+/// The returned future must be polled from within [`run`](crate::run).
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Waits until `deadline` is reached.
+///
+/// The returned future must be polled from within [`run`](crate::run).
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    // Every timer gets a unique ID so that timers with the same deadline can't be mixed up.
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    Sleep {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        registered: false,
+    }
+}
+
+/// A future returned by [`sleep`] and [`sleep_until`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Sleep {
+    deadline: Instant,
+    id: u64,
+    /// Whether this timer currently has an entry in the runtime's [`Timers`].
+    registered: bool,
+}
+
+impl Sleep {
+    /// Returns the instant at which this future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Changes the instant at which this future completes.
+    ///
+    /// This can be called even after the future has completed, to make it wait again.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.deregister();
+        self.deadline = deadline;
+    }
+
+    fn deregister(&mut self) {
+        if self.registered {
+            context::try_with(|context| {
+                context
+                    .timers
+                    .borrow_mut()
+                    .deregister(self.deadline, self.id)
+            });
+            self.registered = false;
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            self.deregister();
+            return Poll::Ready(());
+        }
+
+        context::with(|context| {
+            context
+                .timers
+                .borrow_mut()
+                .register(self.deadline, self.id, cx.waker())
+        });
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+/// Creates a stream which yields every `period`, starting immediately.
+///
+/// The stream must be polled from within [`run`](crate::run).
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Creates a stream which yields every `period`, starting at `start`.
+///
+/// The stream must be polled from within [`run`](crate::run).
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(period > Duration::ZERO, "`period` must be non-zero");
+
+    Interval {
+        sleep: sleep_until(start),
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+/// What an [`Interval`] should do when it falls behind by one or more ticks, e.g. because the
+/// event loop was blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// Yield all the missed ticks as fast as possible to catch up, then carry on with the
+    /// original schedule.
+    #[default]
+    Burst,
+    /// Yield one tick straight away, and schedule the following ticks relative to it.
+    Delay,
+    /// Yield one tick straight away, then skip ahead to the next tick of the original schedule.
+    ///
+    /// This is usually what you want for frame ticks.
+    Skip,
+}
+
+impl MissedTickBehavior {
+    /// Returns when the tick after a late tick, due at `deadline` but handled at `now`, should
+    /// happen.
+    fn next_deadline(self, deadline: Instant, now: Instant, period: Duration) -> Instant {
+        match self {
+            MissedTickBehavior::Burst => deadline + period,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                let behind = (now - deadline).as_nanos() % period.as_nanos();
+                // `behind` is less than `period`, so it fits in a `u64` if `period` does.
+                now + period - Duration::from_nanos(behind as u64)
+            }
+        }
+    }
+}
+
+/// A stream returned by [`interval`] and [`interval_at`], which yields the instant each tick was
+/// scheduled for.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl Interval {
+    /// Waits for the next tick, returning the instant it was scheduled for.
+    pub fn tick(&mut self) -> Tick<'_> {
+        Tick(self)
+    }
+
+    /// Polls for the next tick, returning the instant it was scheduled for.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let deadline = self.sleep.deadline();
+        let now = Instant::now();
+        let next = if now >= deadline + self.period {
+            self.missed_tick_behavior
+                .next_deadline(deadline, now, self.period)
+        } else {
+            deadline + self.period
+        };
+        self.sleep.reset(next);
+
+        Poll::Ready(deadline)
+    }
+
+    /// Restarts the interval, so that the next tick happens one period from now.
+    pub fn reset(&mut self) {
+        self.sleep.reset(Instant::now() + self.period);
+    }
+
+    /// Returns the time between ticks.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns what the interval does when it falls behind.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Sets what the interval does when it falls behind. Defaults to
+    /// [`MissedTickBehavior::Burst`].
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.poll_tick(cx).map(Some)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// A future returned by [`Interval::tick`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Tick<'a>(&'a mut Interval);
+
+impl Future for Tick<'_> {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Instant> {
+        self.0.poll_tick(cx)
+    }
+}
+
+/// The pending timers of a runtime, ordered by deadline.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    wakers: BTreeMap<(Instant, u64), Waker>,
+}
+
+impl Timers {
+    fn register(&mut self, deadline: Instant, id: u64, waker: &Waker) {
+        match self.wakers.get_mut(&(deadline, id)) {
+            Some(old) if old.will_wake(waker) => {}
+            Some(old) => *old = waker.clone(),
+            None => {
+                self.wakers.insert((deadline, id), waker.clone());
+            }
+        }
+    }
+
+    fn deregister(&mut self, deadline: Instant, id: u64) {
+        self.wakers.remove(&(deadline, id));
+    }
+
+    /// Removes all the timers whose deadlines have passed, returning their wakers.
+    ///
+    /// The wakers are returned rather than woken directly so that they can be woken once the
+    /// timers are no longer borrowed.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<Waker> {
+        let pending = self.wakers.split_off(&(now, u64::MAX));
+        let expired = mem::replace(&mut self.wakers, pending);
+        expired.into_values().collect()
+    }
+
+    /// Returns the earliest deadline of any pending timer.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.wakers.keys().next().map(|&(deadline, _)| deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use futures_util::{FutureExt, StreamExt};
+    use winit::event_loop::ControlFlow;
+
+    use super::{interval_at, sleep, sleep_until, MissedTickBehavior};
+    use crate::backend::fake::FakeEventLoop;
+    use crate::context;
+
+    #[test]
+    fn sleep_waits_until_its_deadline() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(30);
+
+        crate::run(event_loop, move |_, _| async move {
+            sleep_until(deadline).await;
+            sleep(Duration::from_millis(20)).await;
+        });
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // The event loop should have waited for the timer, rather than spinning until it fired.
+        let control_flows = proxy.control_flows();
+        assert!(control_flows.contains(&ControlFlow::WaitUntil(deadline)));
+        assert!(!control_flows.contains(&ControlFlow::Poll));
+    }
+
+    #[test]
+    fn dropped_sleeps_stop_waking_the_event_loop() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let output = Rc::new(Cell::new(None));
+        let result = output.clone();
+
+        crate::run(event_loop, move |_, _| async move {
+            let next_deadline = || context::with(|context| context.timers.borrow().next_deadline());
+
+            let mut sleep = sleep(Duration::from_secs(60));
+            assert!((&mut sleep).now_or_never().is_none());
+            let registered = next_deadline();
+            drop(sleep);
+            result.set(Some((registered.is_some(), next_deadline())));
+        });
+        assert_eq!(output.get(), Some((true, None)));
+    }
+
+    #[test]
+    fn interval_ticks_on_schedule() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let start = Instant::now();
+        let period = Duration::from_millis(20);
+        let ticks = Rc::new(Cell::new(Vec::new()));
+        let output = ticks.clone();
+
+        crate::run(event_loop, move |_, _| async move {
+            output.set(interval_at(start, period).take(4).collect().await);
+        });
+
+        let expected: Vec<_> = (0..4).map(|i| start + period * i).collect();
+        assert_eq!(ticks.take(), expected);
+        assert!(start.elapsed() >= period * 3);
+    }
+
+    #[test]
+    fn missed_ticks() {
+        let deadline = Instant::now();
+        let period = Duration::from_millis(10);
+        let now = deadline + Duration::from_millis(25);
+
+        let next = |behavior: MissedTickBehavior| behavior.next_deadline(deadline, now, period);
+        assert_eq!(next(MissedTickBehavior::Burst), deadline + period);
+        assert_eq!(next(MissedTickBehavior::Delay), now + period);
+        assert_eq!(next(MissedTickBehavior::Skip), deadline + period * 3);
+    }
+}
@@ -0,0 +1,102 @@
+//! Running the futures driven by [`run`](crate::run) inside a tokio runtime's context.
+//!
+//! tokio's reactor and timers live on the runtime's own threads, and wake whatever's waiting on
+//! them from there. Since the event loop's waker works from any thread, futures polled by `run`
+//! can use them directly: all they need is for the runtime to be entered while they're polled.
+
+use tokio::runtime::{self, Handle, Runtime};
+
+/// The tokio runtime used by a [`Runtime`](crate::Runtime).
+#[derive(Debug)]
+pub(crate) struct TokioBridge {
+    /// The runtime we started ourselves, if we weren't given a handle to an existing one.
+    runtime: Option<Runtime>,
+    handle: Handle,
+}
+
+impl TokioBridge {
+    /// Uses the runtime behind `handle`, or starts a new multi-threaded runtime if there isn't
+    /// one.
+    pub(crate) fn new(handle: Option<Handle>) -> Self {
+        match handle {
+            Some(handle) => Self {
+                runtime: None,
+                handle,
+            },
+            None => {
+                let runtime = runtime::Builder::new_multi_thread()
+                    .thread_name("winit-async-tokio")
+                    .enable_all()
+                    .build()
+                    .expect("failed to start the tokio runtime");
+                Self {
+                    handle: runtime.handle().clone(),
+                    runtime: Some(runtime),
+                }
+            }
+        }
+    }
+
+    /// Enters the runtime's context until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> runtime::EnterGuard<'_> {
+        self.handle.enter()
+    }
+}
+
+impl Drop for TokioBridge {
+    fn drop(&mut self) {
+        // Don't hold up the event loop exiting on whatever's still running in the background.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use tokio::runtime;
+
+    use crate::backend::fake::FakeEventLoop;
+    use crate::Runtime;
+
+    /// Returns the name of the thread tokio runs a spawned task on.
+    async fn task_thread_name() -> Option<String> {
+        tokio::spawn(async { thread::current().name().map(String::from) })
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn futures_are_polled_inside_the_runtime() {
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let output = crate::run_return(&mut event_loop, |_, _| async {
+            // These only get woken up from tokio's threads.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            task_thread_name().await
+        });
+        assert_eq!(output.unwrap().as_deref(), Some("winit-async-tokio"));
+    }
+
+    #[test]
+    fn futures_are_polled_inside_the_runtime_they_are_given() {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("given")
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut event_loop = FakeEventLoop::<()>::new().wait_for_proxies(Duration::from_secs(10));
+
+        let output = Runtime::new()
+            .tokio_handle(runtime.handle().clone())
+            .run_return(&mut event_loop, |_, _| async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                task_thread_name().await
+            });
+        assert_eq!(output.unwrap().as_deref(), Some("given"));
+    }
+}
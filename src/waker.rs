@@ -0,0 +1,296 @@
+//! The waker which gets the event loop to poll the future again.
+//!
+//! Waking the event loop means sending it an event through its proxy, which isn't free, so the
+//! waker tracks what the event loop is up to in a [`WakeState`] and only sends an event when it
+//! would otherwise miss the wake-up.
+
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+
+// loom is only a dev-dependency, so it can only be used in tests.
+#[cfg(all(test, winit_async_loom))]
+use loom::sync::atomic::{AtomicU8, Ordering};
+#[cfg(not(all(test, winit_async_loom)))]
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::backend::Proxy;
+use crate::{Message, UserEvent};
+
+/// Nothing's going on: the next wake needs to send an event.
+const IDLE: u8 = 0;
+/// An event has been sent, and the event loop hasn't started polling since.
+const SCHEDULED: u8 = 1;
+/// The event loop is polling, and will look at anything woken up before it started.
+const POLLING: u8 = 2;
+/// The event loop was woken while it was polling, and needs to poll again afterwards.
+const NOTIFIED: u8 = 3;
+
+/// Where the event loop is at with respect to wake-ups.
+///
+/// Every wake either sends the event loop exactly one event or gets picked up by a poll which is
+/// already underway, so none of them get lost.
+#[derive(Debug)]
+pub(crate) struct WakeState {
+    state: AtomicU8,
+}
+
+impl WakeState {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: AtomicU8::new(IDLE),
+        }
+    }
+
+    /// Records a wake-up, returning whether it's up to the caller to send the event loop an event.
+    pub(crate) fn wake(&self) -> bool {
+        let mut current = self.state.load(Ordering::Relaxed);
+        loop {
+            let new = match current {
+                IDLE => SCHEDULED,
+                POLLING => NOTIFIED,
+                // Someone else has already made sure the event loop will poll again, but this
+                // still needs to be written back below, so that anything written before this
+                // wake is released to that poll.
+                SCHEDULED | NOTIFIED => current,
+                _ => unreachable!("invalid wake state {current}"),
+            };
+
+            match self.state.compare_exchange_weak(
+                current,
+                new,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return current == IDLE,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Marks the event loop as being about to poll.
+    ///
+    /// Any wake-ups up to this point will be seen by the poll, so they can be forgotten about.
+    /// This can be called several times before [`finish_poll`](Self::finish_poll).
+    pub(crate) fn start_poll(&self) {
+        self.state.swap(POLLING, Ordering::AcqRel);
+    }
+
+    /// Marks the event loop as being done polling, returning whether it was woken up in the
+    /// meantime.
+    ///
+    /// If it was, the caller needs to wake it up again, since nobody else has sent it an event.
+    pub(crate) fn finish_poll(&self) -> bool {
+        self.state.swap(IDLE, Ordering::AcqRel) == NOTIFIED
+    }
+}
+
+/// Creates a waker which wakes the event loop through `proxy`, sharing `state` with the event
+/// loop.
+pub(crate) fn create_waker<T: 'static>(proxy: impl Proxy<T>, state: Arc<WakeState>) -> Waker {
+    struct ProxyWaker<T, P> {
+        // `EventLoopProxy` isn't `Sync` on every platform, so this needs a lock. `state` means
+        // it's almost always uncontended, though.
+        proxy: Mutex<P>,
+        state: Arc<WakeState>,
+        _event: PhantomData<fn(T)>,
+    }
+
+    impl<T: 'static, P: Proxy<T>> Wake for ProxyWaker<T, P> {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref()
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            if self.state.wake() {
+                self.proxy.lock().unwrap().send(UserEvent(Message::Wake));
+            }
+        }
+    }
+
+    Arc::new(ProxyWaker {
+        proxy: Mutex::new(proxy),
+        state,
+        _event: PhantomData,
+    })
+    .into()
+}
+
+#[cfg(all(test, winit_async_loom))]
+mod tests {
+    //! Model checks for [`WakeState`], run with:
+    //!
+    //! ```sh
+    //! RUSTFLAGS="--cfg winit_async_loom" cargo test --release --lib waker
+    //! ```
+    //!
+    //! The event loop's side of these follows what `Driver` does with each batch of events,
+    //! including the events it returns early from while it's waiting for the end of the batch.
+
+    use std::mem;
+
+    use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use loom::sync::{Arc, Mutex};
+    use loom::thread;
+
+    use super::WakeState;
+
+    /// The state shared between the event loop and the threads waking it up.
+    #[derive(Default)]
+    struct Shared {
+        state: WakeState,
+        /// Something for the poll to pick up, like the output of a background task.
+        data: AtomicBool,
+        /// The number of jobs pushed, standing in for `JobQueue`: a second source of wake-ups,
+        /// which gets looked at separately from the poll.
+        jobs: Mutex<usize>,
+        /// The number of events sent through the proxy.
+        sent: AtomicUsize,
+    }
+
+    impl Default for WakeState {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// What the event loop has seen so far.
+    #[derive(Default)]
+    struct Seen {
+        data: bool,
+        jobs: usize,
+    }
+
+    impl Shared {
+        fn wake(&self) {
+            if self.state.wake() {
+                self.sent.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        /// Sets `data` and then wakes the event loop, like a background task completing.
+        fn notify(&self) {
+            self.data.store(true, Ordering::Relaxed);
+            self.wake();
+        }
+
+        /// Pushes a job and then wakes the event loop, like `MainThreadHandle::run_on_main`.
+        fn push_job(&self) {
+            *self.jobs.lock().unwrap() += 1;
+            self.wake();
+        }
+
+        /// Handles a batch of events, made up of `early` events which get returned from before
+        /// polling (as in `PollMode::Batched`), followed by one which polls.
+        fn handle_batch(&self, early: usize, seen: &mut Seen) {
+            for _ in 0..early {
+                self.state.start_poll();
+            }
+
+            // Like the event which polls, this starts polling once at the top, and then again
+            // once it's caught up on timers and is about to poll.
+            self.state.start_poll();
+            self.state.start_poll();
+            seen.jobs += mem::take(&mut *self.jobs.lock().unwrap());
+            seen.data |= self.data.load(Ordering::Relaxed);
+            if self.state.finish_poll() {
+                self.wake();
+            }
+        }
+
+        /// Handles a batch, then another one for every event sent through the proxy, once
+        /// everything else has finished.
+        fn run(&self, early: usize, others: Vec<thread::JoinHandle<()>>) -> Seen {
+            let mut seen = Seen::default();
+            self.handle_batch(early, &mut seen);
+            for thread in others {
+                thread.join().unwrap();
+            }
+
+            let mut handled = 0;
+            while handled < self.sent.load(Ordering::Relaxed) {
+                handled += 1;
+                self.handle_batch(early, &mut seen);
+            }
+            seen
+        }
+    }
+
+    fn spawn(shared: &Arc<Shared>, f: fn(&Shared)) -> thread::JoinHandle<()> {
+        let shared = shared.clone();
+        thread::spawn(move || f(&shared))
+    }
+
+    #[test]
+    fn wake_during_batch_is_not_lost() {
+        for early in [0, 1] {
+            loom::model(move || {
+                let shared = Arc::new(Shared::default());
+                let notifier = spawn(&shared, Shared::notify);
+                let seen = shared.run(early, vec![notifier]);
+
+                assert!(seen.data);
+                assert!(shared.sent.load(Ordering::Relaxed) <= 1);
+            });
+        }
+    }
+
+    #[test]
+    fn job_pushed_during_batch_is_run() {
+        for early in [0, 1] {
+            loom::model(move || {
+                let shared = Arc::new(Shared::default());
+                let pusher = spawn(&shared, Shared::push_job);
+                let seen = shared.run(early, vec![pusher]);
+
+                assert_eq!(seen.jobs, 1);
+                assert!(shared.sent.load(Ordering::Relaxed) <= 1);
+            });
+        }
+    }
+
+    #[test]
+    fn wakes_from_both_sources_are_not_lost() {
+        // Exploring every interleaving of three threads takes far too long.
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let shared = Arc::new(Shared::default());
+            let notifier = spawn(&shared, Shared::notify);
+            let pusher = spawn(&shared, Shared::push_job);
+            let seen = shared.run(1, vec![notifier, pusher]);
+
+            assert!(seen.data);
+            assert_eq!(seen.jobs, 1);
+            assert!(shared.sent.load(Ordering::Relaxed) <= 2);
+        });
+    }
+
+    #[test]
+    fn wake_while_scheduled_is_not_lost() {
+        loom::model(|| {
+            let shared = Arc::new(Shared::default());
+            // An earlier wake has already sent an event, which the event loop is about to handle.
+            shared.wake();
+
+            let notifier = spawn(&shared, Shared::notify);
+            let seen = shared.run(1, vec![notifier]);
+
+            assert!(seen.data);
+            assert!(shared.sent.load(Ordering::Relaxed) <= 2);
+        });
+    }
+
+    #[test]
+    fn concurrent_wakes_send_one_event() {
+        loom::model(|| {
+            let shared = Arc::new(Shared::default());
+            let threads: Vec<_> = (0..2).map(|_| spawn(&shared, Shared::wake)).collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            assert_eq!(shared.sent.load(Ordering::Relaxed), 1);
+        });
+    }
+}
@@ -0,0 +1,394 @@
+//! Per-window event streams, and waiting for windows to be redrawn.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_channel::{Receiver, Sender};
+use futures_core::Stream;
+use winit::window::{Window, WindowId};
+
+use crate::context;
+use crate::event::WindowEvent;
+
+/// A stream of the events received by a single window, returned by
+/// [`Events::for_window`](crate::Events::for_window).
+///
+/// The stream ends after yielding [`WindowEvent::Destroyed`], or when the event loop is destroyed.
+#[derive(Debug)]
+pub struct WindowEvents {
+    window_id: WindowId,
+    rx: Receiver<WindowEvent>,
+}
+
+impl WindowEvents {
+    /// Returns the ID of the window this stream is receiving events for.
+    pub fn window_id(&self) -> WindowId {
+        self.window_id
+    }
+}
+
+impl Stream for WindowEvents {
+    type Item = WindowEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rx.size_hint()
+    }
+}
+
+/// Returns a stream of `window`'s frames, for writing a render loop.
+///
+/// Each time the stream is polled for a new frame, it calls [`Window::request_redraw`] and then
+/// waits for the resulting [`Event::RedrawRequested`](crate::event::Event::RedrawRequested). The
+/// frame is yielded while that event is being dispatched, so rendering it straight away is the
+/// same as rendering in response to the event.
+///
+/// The stream ends once the window is destroyed, or when the event loop is destroyed.
+///
+/// The returned stream must be polled from within [`run`](crate::run).
+pub fn window_frames(window: &Window) -> WindowFrames<'_> {
+    WindowFrames {
+        window,
+        frames: Frames::new(window.id()),
+    }
+}
+
+/// Requests a redraw of `window`, and waits for it to happen.
+///
+/// This is the same as getting the next item of [`window_frames`]: it resolves to `None` if the
+/// window is destroyed first.
+///
+/// The returned future must be polled from within [`run`](crate::run).
+pub fn next_frame(window: &Window) -> NextFrame<'_> {
+    NextFrame {
+        frames: window_frames(window),
+    }
+}
+
+/// A redraw of a window, yielded by [`WindowFrames`] and [`NextFrame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    window_id: WindowId,
+    time: Instant,
+    delta: Option<Duration>,
+}
+
+impl Frame {
+    /// Returns the ID of the window being redrawn.
+    pub fn window_id(&self) -> WindowId {
+        self.window_id
+    }
+
+    /// Returns when the event loop started dispatching this frame's
+    /// [`Event::RedrawRequested`](crate::event::Event::RedrawRequested).
+    pub fn time(&self) -> Instant {
+        self.time
+    }
+
+    /// Returns how long it's been since the window's previous frame, or `None` if this is its
+    /// first.
+    ///
+    /// Every redraw of the window counts, not just those waited on with [`window_frames`] or
+    /// [`next_frame`].
+    pub fn delta(&self) -> Option<Duration> {
+        self.delta
+    }
+}
+
+/// A stream returned by [`window_frames`].
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct WindowFrames<'a> {
+    window: &'a Window,
+    frames: Frames,
+}
+
+impl WindowFrames<'_> {
+    /// Returns the ID of the window this stream is yielding frames for.
+    pub fn window_id(&self) -> WindowId {
+        self.frames.window_id
+    }
+}
+
+impl Stream for WindowFrames<'_> {
+    type Item = Frame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame>> {
+        let window = self.window;
+        self.frames.poll_next(cx, || window.request_redraw())
+    }
+}
+
+/// The part of [`WindowFrames`] which doesn't need the window itself, only a way to request a
+/// redraw of it.
+#[derive(Debug)]
+struct Frames {
+    window_id: WindowId,
+    /// Where the frame we're waiting for will arrive, if we've requested one.
+    rx: Option<Receiver<Frame>>,
+    ended: bool,
+}
+
+impl Frames {
+    fn new(window_id: WindowId) -> Self {
+        Self {
+            window_id,
+            rx: None,
+            ended: false,
+        }
+    }
+
+    /// Polls for the next frame, calling `request_redraw` if one hasn't been requested yet.
+    fn poll_next(
+        &mut self,
+        cx: &mut Context<'_>,
+        request_redraw: impl FnOnce(),
+    ) -> Poll<Option<Frame>> {
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        let window_id = self.window_id;
+        let rx = self.rx.get_or_insert_with(|| {
+            let rx =
+                context::with(|context| context.windows.borrow_mut().wait_for_frame(window_id));
+            request_redraw();
+            rx
+        });
+
+        let frame = match Pin::new(rx).poll_next(cx) {
+            Poll::Ready(frame) => frame,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.rx = None;
+        self.ended = frame.is_none();
+        Poll::Ready(frame)
+    }
+}
+
+/// A future returned by [`next_frame`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct NextFrame<'a> {
+    frames: WindowFrames<'a>,
+}
+
+impl Future for NextFrame<'_> {
+    type Output = Option<Frame>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame>> {
+        Pin::new(&mut self.frames).poll_next(cx)
+    }
+}
+
+/// The senders for all the [`WindowEvents`] streams of a runtime, and the [`WindowFrames`]
+/// streams waiting for their windows to be redrawn.
+#[derive(Debug, Default)]
+pub(crate) struct WindowRegistry {
+    senders: HashMap<WindowId, Vec<Sender<WindowEvent>>>,
+    frames: HashMap<WindowId, FrameState>,
+}
+
+#[derive(Debug, Default)]
+struct FrameState {
+    /// When the window was last redrawn.
+    last: Option<Instant>,
+    waiters: Vec<Sender<Frame>>,
+}
+
+impl WindowRegistry {
+    pub(crate) fn subscribe(&mut self, window_id: WindowId) -> WindowEvents {
+        let (tx, rx) = async_channel::unbounded();
+        self.senders.entry(window_id).or_default().push(tx);
+        WindowEvents { window_id, rx }
+    }
+
+    fn wait_for_frame(&mut self, window_id: WindowId) -> Receiver<Frame> {
+        let (tx, rx) = async_channel::bounded(1);
+        self.frames.entry(window_id).or_default().waiters.push(tx);
+        rx
+    }
+
+    /// Records that a window is being redrawn, and sends the frame to everything waiting for it.
+    pub(crate) fn redraw(&mut self, window_id: WindowId, time: Instant) {
+        let state = self.frames.entry(window_id).or_default();
+        let frame = Frame {
+            window_id,
+            time,
+            delta: state.last.map(|last| time.saturating_duration_since(last)),
+        };
+        state.last = Some(time);
+        for tx in state.waiters.drain(..) {
+            let _ = tx.try_send(frame);
+        }
+    }
+
+    /// Sends an event to all the streams for the window it was received by.
+    pub(crate) fn dispatch(&mut self, window_id: WindowId, event: &WindowEvent) {
+        if matches!(event, WindowEvent::Destroyed) {
+            // The window won't be redrawn again, so end its frame streams.
+            self.frames.remove(&window_id);
+        }
+
+        let senders = match self.senders.get_mut(&window_id) {
+            Some(senders) => senders,
+            None => return,
+        };
+
+        // Take the chance to get rid of any streams which have been dropped.
+        senders.retain(|tx| tx.try_send(event.clone()).is_ok());
+
+        // The window's gone, so drop its senders to end the streams.
+        if senders.is_empty() || matches!(event, WindowEvent::Destroyed) {
+            self.senders.remove(&window_id);
+        }
+    }
+
+    /// Ends all the streams.
+    pub(crate) fn close_all(&mut self) {
+        self.senders.clear();
+        self.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::future;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use super::{Frame, Frames};
+    use crate::backend::fake::{self, FakeEventLoop};
+    use crate::event::{Event, WindowEvent};
+
+    /// Waits for the next frame like [`next_frame`](super::next_frame), but using
+    /// `request_redraw` in place of a real window.
+    async fn next_frame(frames: &mut Frames, request_redraw: impl Fn()) -> Option<Frame> {
+        future::poll_fn(|cx| frames.poll_next(cx, &request_redraw)).await
+    }
+
+    #[test]
+    fn window_streams_get_their_windows_events() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+        let window_id = fake::window_id();
+        let output = Rc::new(RefCell::new(None));
+        let result = output.clone();
+
+        crate::run(event_loop, move |_, mut events| async move {
+            let window_events = events.for_window(window_id);
+            for event in [
+                WindowEvent::Focused(true),
+                WindowEvent::CloseRequested,
+                WindowEvent::Destroyed,
+            ] {
+                proxy.push_event(Event::WindowEvent { window_id, event });
+            }
+            proxy.push_event(Event::UserEvent(()));
+
+            let window_events: Vec<_> = window_events.collect().await;
+            // The root stream still gets everything, including what comes after the window's
+            // destroyed.
+            let mut saw_user_event = false;
+            while let Some(event) = events.next().await {
+                if let Event::UserEvent(()) = event {
+                    saw_user_event = true;
+                    break;
+                }
+            }
+            *result.borrow_mut() = Some((window_events, saw_user_event));
+        });
+
+        let (window_events, saw_user_event) = output.take().unwrap();
+        assert!(saw_user_event);
+        assert_eq!(
+            window_events,
+            [
+                WindowEvent::Focused(true),
+                WindowEvent::CloseRequested,
+                WindowEvent::Destroyed,
+            ]
+        );
+    }
+
+    #[test]
+    fn window_streams_end_with_the_event_loop() {
+        let event_loop = FakeEventLoop::<()>::new();
+        let output = Rc::new(RefCell::new(None));
+        let result = output.clone();
+
+        crate::run(event_loop, move |_, events| async move {
+            let mut window_events = events.for_window(fake::window_id());
+            *result.borrow_mut() = Some(window_events.next().await);
+        });
+        assert_eq!(output.take(), Some(None));
+    }
+
+    #[test]
+    fn frames_know_how_long_its_been_since_the_last_one() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+        let window_id = fake::window_id();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async move {
+            let redraw = || proxy.push_event(Event::RedrawRequested(window_id));
+            let mut frames = Frames::new(window_id);
+
+            let first = next_frame(&mut frames, redraw).await.unwrap();
+            crate::sleep(Duration::from_millis(5)).await;
+            // Redraws which nothing's waiting for count as well.
+            redraw();
+            crate::sleep(Duration::from_millis(5)).await;
+            let last = next_frame(&mut frames, redraw).await.unwrap();
+            (first, last)
+        });
+
+        let (first, last) = output.unwrap();
+        assert_eq!(first.window_id(), window_id);
+        assert_eq!(first.delta(), None);
+        let delta = last.delta().unwrap();
+        assert!(delta >= Duration::from_millis(5));
+        assert!(delta < last.time() - first.time());
+    }
+
+    #[test]
+    fn frames_end_when_the_window_is_destroyed() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+        let proxy = event_loop.create_proxy();
+        let window_id = fake::window_id();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async move {
+            let destroy = || {
+                proxy.push_event(Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::Destroyed,
+                })
+            };
+            let mut frames = Frames::new(window_id);
+            let frame = next_frame(&mut frames, destroy).await;
+            let after = next_frame(&mut frames, || panic!("the stream should have ended")).await;
+            (frame, after)
+        });
+        assert_eq!(output.unwrap(), (None, None));
+    }
+
+    #[test]
+    fn frames_end_with_the_event_loop() {
+        let mut event_loop = FakeEventLoop::<()>::new();
+
+        let output = crate::run_return(&mut event_loop, |_, _| async move {
+            // Nothing's going to redraw the window, so this waits until the event loop's gone.
+            next_frame(&mut Frames::new(fake::window_id()), || {}).await
+        });
+        assert_eq!(output.unwrap(), None);
+    }
+}